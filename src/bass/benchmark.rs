@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+use crate::bass::note::{cents_from_midi, midi_to_frequency, note_name, MAX_FRET};
+use crate::bass::harmonics::OctaveCorrector;
+use crate::bass::pitch_detector::{playable_range, DetectorAlgorithm};
+use crate::bass::synth::{pluck, Noise};
+use crate::bass::tuning::Tuning;
+use crate::settings::GateSettings;
+
+const SAMPLE_RATE: usize = 48000;
+const WINDOW_SIZE: usize = 4096;
+// where in the note the window starts, just after the attack
+const ATTACK_SECONDS: f64 = 0.02;
+// how far off an estimate can be and still count as the right note
+const CORRECT_CENTS: f64 = 50.;
+
+// Tallies for one detector over every note of the benchmark
+#[derive(Default)]
+struct BenchmarkResult {
+    notes: usize,
+    correct: usize,
+    octave_errors: usize,
+    no_pitch: usize,
+    cents_error: f64,
+    total_time: Duration,
+    max_time: Duration,
+    worst_note: Option<(i32, f64)>,
+}
+
+// Runs every detector over synthesized notes on every string and fret of a six string bass and
+// prints how often each got it right and how long it took, for --benchmark-detectors
+pub fn run_benchmark() {
+    let tuning = Tuning::six_string();
+    let midis: Vec<i32> = tuning.strings.iter()
+        .flat_map(|string| (0..=MAX_FRET as i32).map(move |fret| string.midi + fret))
+        .collect();
+    let mut noise = Noise::default();
+    let signals: Vec<(i32, Vec<f64>)> = midis.iter()
+        .map(|midi| (*midi, pluck(midi_to_frequency(*midi as f64), SAMPLE_RATE, ATTACK_SECONDS, WINDOW_SIZE, &mut noise)))
+        .collect();
+
+    println!(
+        "{} notes ({} strings x {} frets) at {} Hz, {} sample window ({:.1} ms)",
+        signals.len(), tuning.strings.len(), MAX_FRET + 1, SAMPLE_RATE, WINDOW_SIZE,
+        WINDOW_SIZE as f64 / SAMPLE_RATE as f64 * 1000.,
+    );
+
+    let (min_frequency, max_frequency) = playable_range();
+    let gate = GateSettings::default();
+    // every algorithm on its own and then with the spectrum checking its octave
+    let runs = [false, true].into_iter()
+        .flat_map(|octave_correction| DetectorAlgorithm::ALL.map(|algorithm| (algorithm, octave_correction)));
+
+    for (algorithm, octave_correction) in runs {
+        let mut detector = algorithm.build(WINDOW_SIZE);
+        let mut corrector = octave_correction
+            .then(|| OctaveCorrector::new(WINDOW_SIZE, SAMPLE_RATE, min_frequency, max_frequency));
+        let mut result = BenchmarkResult::default();
+
+        for (midi, signal) in &signals {
+            let start = Instant::now();
+            let mut estimate = detector.estimate(signal, SAMPLE_RATE, gate.clarity);
+            if let (Some(estimate), Some(corrector)) = (estimate.as_mut(), corrector.as_mut()) {
+                estimate.frequency = corrector.correct(signal, estimate.frequency);
+            }
+            let elapsed = start.elapsed();
+
+            result.notes += 1;
+            result.total_time += elapsed;
+            result.max_time = result.max_time.max(elapsed);
+
+            let Some(estimate) = estimate else {
+                result.no_pitch += 1;
+                continue;
+            };
+            let cents = cents_from_midi(estimate.frequency, *midi);
+            if cents.abs() <= CORRECT_CENTS {
+                result.correct += 1;
+                result.cents_error += cents.abs();
+            } else {
+                // an octave or two out, give or take half a semitone
+                let octaves = cents / 1200.;
+                if octaves.round() != 0. && (octaves - octaves.round()).abs() * 1200. <= CORRECT_CENTS {
+                    result.octave_errors += 1;
+                }
+                if result.worst_note.map_or(true, |(_, worst)| cents.abs() > worst.abs()) {
+                    result.worst_note = Some((*midi, cents));
+                }
+            }
+        }
+
+        print_result(algorithm, octave_correction, &result);
+    }
+}
+
+fn print_result(algorithm: DetectorAlgorithm, octave_correction: bool, result: &BenchmarkResult) {
+    let percent = |count: usize| count as f64 / result.notes as f64 * 100.;
+
+    if octave_correction {
+        println!("{} with octave correction:", algorithm.name());
+    } else {
+        println!("{}:", algorithm.name());
+    }
+    println!(
+        "  correct {:.1}%, octave errors {:.1}%, other errors {:.1}%, no pitch {:.1}%",
+        percent(result.correct),
+        percent(result.octave_errors),
+        percent(result.notes - result.correct - result.octave_errors - result.no_pitch),
+        percent(result.no_pitch),
+    );
+    if result.correct > 0 {
+        println!("  mean error on correct notes {:.2} cents", result.cents_error / result.correct as f64);
+    }
+    if let Some((midi, cents)) = result.worst_note {
+        println!("  worst miss {} off by {:.0} cents", note_name(midi), cents);
+    }
+    // the window has to fill before anything can be detected, so that's part of the latency too
+    let mean_time = result.total_time / result.notes as u32;
+    let window_ms = WINDOW_SIZE as f64 / SAMPLE_RATE as f64 * 1000.;
+    println!(
+        "  processing mean {:.3} ms, max {:.3} ms, latency with window {:.1} ms",
+        mean_time.as_secs_f64() * 1000.,
+        result.max_time.as_secs_f64() * 1000.,
+        window_ms + mean_time.as_secs_f64() * 1000.,
+    );
+}
@@ -0,0 +1,113 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host};
+use crate::settings::InputSettings;
+
+// An input channel the player can pick in the settings screen
+#[derive(Debug, Clone)]
+pub struct InputOption {
+    pub host: String,
+    pub device: String,
+    // None mixes every channel of the device down
+    pub channel: Option<usize>,
+}
+
+impl InputOption {
+    pub fn label(&self) -> String {
+        format!("{} ({}) - {}", self.device, self.host, channel_label(self.channel))
+    }
+}
+
+pub fn channel_label(channel: Option<usize>) -> String {
+    match channel {
+        Some(channel) => format!("Input {}", channel + 1),
+        None => "All Inputs".to_string(),
+    }
+}
+
+// Every channel of every input device on every audio host available on this machine, plus a
+// mix of all channels for devices that have more than one
+pub fn input_options() -> Vec<InputOption> {
+    let mut options = Vec::new();
+
+    for host_id in cpal::available_hosts() {
+        let Ok(host) = cpal::host_from_id(host_id) else { continue };
+        let Ok(devices) = host.input_devices() else { continue };
+
+        for device in devices {
+            let (Ok(name), Ok(config)) = (device.name(), device.default_input_config()) else { continue };
+            let channels = config.channels() as usize;
+            let mixdown = if channels > 1 { Some(None) } else { None };
+            for channel in mixdown.into_iter().chain((0..channels).map(Some)) {
+                options.push(InputOption {
+                    host: host_id.name().to_string(),
+                    device: name.clone(),
+                    channel,
+                });
+            }
+        }
+    }
+
+    options
+}
+
+// Print every input device for --list-devices
+pub fn list_devices() {
+    for host_id in cpal::available_hosts() {
+        println!("{}:", host_id.name());
+        let Ok(host) = cpal::host_from_id(host_id) else {
+            println!("  (unavailable)");
+            continue;
+        };
+        let default_name = host.default_input_device().and_then(|device| device.name().ok());
+        let Ok(devices) = host.input_devices() else { continue };
+
+        for device in devices {
+            let Ok(name) = device.name() else { continue };
+            let default_marker = if default_name.as_ref() == Some(&name) { " (default)" } else { "" };
+            match device.default_input_config() {
+                Ok(config) => println!(
+                    "  {}{} - {} channel(s), {} Hz, {:?}",
+                    name, default_marker, config.channels(), config.sample_rate().0, config.sample_format()
+                ),
+                Err(err) => println!("  {}{} - {}", name, default_marker, err),
+            }
+        }
+    }
+}
+
+// Finds the device picked in the settings, falling back on the default input if it's gone
+pub fn find_input_device(settings: &InputSettings) -> Option<Device> {
+    let host = settings_host(settings);
+
+    if let Some(name) = &settings.device {
+        let device = find_named_device(&host, name);
+        if device.is_some() {
+            return device;
+        }
+        println!("Input device \"{}\" not found, using the default input", name);
+    }
+
+    host.default_input_device()
+}
+
+// Whether the device picked in the settings is plugged in, without falling back on the default
+pub fn input_device_available(settings: &InputSettings) -> bool {
+    let host = settings_host(settings);
+
+    match &settings.device {
+        Some(name) => find_named_device(&host, name).is_some(),
+        None => host.default_input_device().is_some(),
+    }
+}
+
+fn settings_host(settings: &InputSettings) -> Host {
+    settings.host.as_ref()
+        .and_then(|name| cpal::available_hosts().into_iter().find(|host_id| host_id.name() == name))
+        .and_then(|host_id| cpal::host_from_id(host_id).ok())
+        .unwrap_or_else(cpal::default_host)
+}
+
+fn find_named_device(host: &Host, name: &str) -> Option<Device> {
+    host.input_devices().ok()
+        .and_then(|mut devices| devices.find(|device| device.name().is_ok_and(|device_name| device_name == name)))
+}
@@ -0,0 +1,187 @@
+use std::f64::consts::TAU;
+use crate::settings::FilterSettings;
+
+// how narrow the mains notches are. Hum drifting 0.05 Hz off the mains is still cut by about
+// 16 dB, and G1 (49 Hz) and B1 (61.7 Hz), the notes closest to 50 and 60 Hz, lose under half a dB.
+const NOTCH_Q: f64 = 80.;
+// Butterworth
+const BAND_PASS_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+// the detectors still need the harmonics of the highest notes above this
+const BAND_PASS_HIGH: f64 = 2500.;
+const DC_BLOCKER_CUTOFF: f64 = 5.;
+
+// Cleans the input up before pitch detection: removes any DC offset, notches out mains hum and
+// its harmonics and cuts everything outside what a bass can play. Every stage can be turned off in
+// the settings.
+pub struct PreFilter {
+    dc_blocker: Option<DcBlocker>,
+    biquads: Vec<Biquad>,
+}
+
+impl PreFilter {
+    // `low` is the lowest fundamental that has to get through
+    pub fn new(settings: &FilterSettings, sample_rate: usize, low: f64) -> PreFilter {
+        let sample_rate = sample_rate as f64;
+        let nyquist = sample_rate / 2.;
+        let mut biquads = Vec::new();
+
+        if let Some(mains) = settings.mains_frequency {
+            for harmonic in 1..=settings.mains_harmonics {
+                let frequency = mains * harmonic as f64;
+                if frequency < nyquist {
+                    biquads.push(Biquad::notch(frequency, NOTCH_Q, sample_rate));
+                }
+            }
+        }
+        if settings.band_pass {
+            // an octave under the lowest note so it isn't turned down
+            biquads.push(Biquad::high_pass(low / 2., BAND_PASS_Q, sample_rate));
+            biquads.push(Biquad::low_pass(BAND_PASS_HIGH.min(nyquist * 0.9), BAND_PASS_Q, sample_rate));
+        }
+
+        PreFilter {
+            dc_blocker: settings.dc_blocker.then(|| DcBlocker::new(DC_BLOCKER_CUTOFF, sample_rate)),
+            biquads,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            let mut value = *sample as f64;
+            if let Some(dc_blocker) = self.dc_blocker.as_mut() {
+                value = dc_blocker.process(value);
+            }
+            for biquad in self.biquads.iter_mut() {
+                value = biquad.process(value);
+            }
+            *sample = value as f32;
+        }
+    }
+}
+
+// One pole high pass that takes any constant offset out of the signal
+struct DcBlocker {
+    pole: f64,
+    last_input: f64,
+    last_output: f64,
+}
+
+impl DcBlocker {
+    fn new(cutoff: f64, sample_rate: f64) -> DcBlocker {
+        DcBlocker {
+            pole: 1. - TAU * cutoff / sample_rate,
+            last_input: 0.,
+            last_output: 0.,
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = input - self.last_input + self.pole * self.last_output;
+        self.last_input = input;
+        self.last_output = output;
+        output
+    }
+}
+
+// Second order filter section, with coefficients from the Audio EQ Cookbook
+// (https://www.w3.org/TR/audio-eq-cookbook/)
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    // transposed direct form II state
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.,
+            z2: 0.,
+        }
+    }
+
+    fn notch(frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let (cos, alpha) = Biquad::cos_alpha(frequency, q, sample_rate);
+        Biquad::new(1., -2. * cos, 1., 1. + alpha, -2. * cos, 1. - alpha)
+    }
+
+    fn high_pass(frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let (cos, alpha) = Biquad::cos_alpha(frequency, q, sample_rate);
+        Biquad::new((1. + cos) / 2., -(1. + cos), (1. + cos) / 2., 1. + alpha, -2. * cos, 1. - alpha)
+    }
+
+    fn low_pass(frequency: f64, q: f64, sample_rate: f64) -> Biquad {
+        let (cos, alpha) = Biquad::cos_alpha(frequency, q, sample_rate);
+        Biquad::new((1. - cos) / 2., 1. - cos, (1. - cos) / 2., 1. + alpha, -2. * cos, 1. - alpha)
+    }
+
+    fn cos_alpha(frequency: f64, q: f64, sample_rate: f64) -> (f64, f64) {
+        let omega = TAU * frequency / sample_rate;
+        (omega.cos(), omega.sin() / (2. * q))
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bass::note::midi_to_frequency;
+
+    const SAMPLE_RATE: usize = 48000;
+    // long enough for the narrowest notch to settle
+    const SECONDS: usize = 6;
+
+    // Gain in dB of a steady sine once the filter has settled, with only the mains notches on
+    fn notch_gain(mains: f64, frequency: f64) -> f64 {
+        let settings = FilterSettings {
+            dc_blocker: false,
+            mains_frequency: Some(mains),
+            mains_harmonics: 4,
+            band_pass: false,
+        };
+        let mut filter = PreFilter::new(&settings, SAMPLE_RATE, 30.);
+
+        let mut signal: Vec<f32> = (0..SAMPLE_RATE * SECONDS)
+            .map(|sample| (TAU * frequency * sample as f64 / SAMPLE_RATE as f64).sin() as f32)
+            .collect();
+        filter.process(&mut signal);
+
+        let settled = &signal[SAMPLE_RATE * (SECONDS - 1)..];
+        let rms = (settled.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / settled.len() as f64).sqrt();
+        20. * (rms * 2f64.sqrt()).log10()
+    }
+
+    #[test]
+    fn mains_notch_cuts_hum_and_its_harmonics() {
+        for mains in [50., 60.] {
+            for harmonic in 1..=4 {
+                let gain = notch_gain(mains, mains * harmonic as f64);
+                assert!(gain < -30., "{} Hz only cut by {} dB", mains * harmonic as f64, -gain);
+            }
+        }
+    }
+
+    #[test]
+    fn mains_notch_leaves_the_nearest_notes_alone() {
+        // G1 and G#1 either side of 50 Hz, A#1 and B1 either side of 60 Hz
+        for (mains, midi) in [(50., 31), (50., 32), (60., 34), (60., 35)] {
+            let gain = notch_gain(mains, midi_to_frequency(midi as f64));
+            assert!(gain.abs() < 0.5, "midi {} changed by {} dB", midi, gain);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+// the spectrum is zero padded to this many times the window for finer bins
+const ZERO_PADDING: usize = 2;
+// how many harmonics of a candidate fundamental are looked at
+const HARMONICS: usize = 8;
+// harmonics above this are too weak on a bass to tell anything
+const MAX_HARMONIC_FREQUENCY: f64 = 2000.;
+// a fundamental needs at least this much energy in its own harmonics, compared to the ones it
+// shares with the octave or fifth above, to be believed
+const OWN_HARMONIC_RATIO: f64 = 0.35;
+
+// Time domain detectors pick a multiple of the period now and then, reading a note an octave (or
+// an octave and a fifth) too low, or lock on to the second harmonic when the fundamental is weak.
+// The spectrum tells these apart: a fundamental has energy at every multiple of itself, while a
+// sub-octave is missing every other one.
+pub struct OctaveCorrector {
+    fft: Arc<dyn Fft<f64>>,
+    hann: Vec<f64>,
+    buffer: Vec<Complex<f64>>,
+    scratch: Vec<Complex<f64>>,
+    spectrum: Vec<f64>,
+    sample_rate: usize,
+    min_frequency: f64,
+    max_frequency: f64,
+    // harmonics closer together than this blur into each other
+    min_spacing: f64,
+}
+
+impl OctaveCorrector {
+    pub fn new(window_size: usize, sample_rate: usize, min_frequency: f64, max_frequency: f64) -> OctaveCorrector {
+        let fft_size = window_size * ZERO_PADDING;
+        let fft = FftPlanner::new().plan_fft_forward(fft_size);
+        let scratch = vec![Complex::default(); fft.get_inplace_scratch_len()];
+
+        OctaveCorrector {
+            fft,
+            hann: (0..window_size)
+                .map(|sample| 0.5 - 0.5 * (TAU * sample as f64 / window_size as f64).cos())
+                .collect(),
+            buffer: vec![Complex::default(); fft_size],
+            scratch,
+            spectrum: vec![0.; fft_size / 2],
+            sample_rate,
+            min_frequency,
+            max_frequency,
+            // the hann window smears each peak over two bins (of the unpadded window) either side
+            min_spacing: 2.5 * sample_rate as f64 / window_size as f64,
+        }
+    }
+
+    // Checks the detector's estimate against the spectrum of the window it came from and returns
+    // the frequency of the fundamental
+    pub fn correct(&mut self, signal: &[f64], frequency: f64) -> f64 {
+        self.analyse(signal);
+        let mut frequency = frequency;
+
+        // too low for any string, so it's a sub-harmonic of the note. Go with the lowest multiple
+        // in range that isn't a sub-harmonic itself.
+        if frequency < self.min_frequency {
+            if let Some(multiple) = [2., 3., 4.].map(|step| frequency * step).into_iter().find(|multiple| {
+                *multiple >= self.min_frequency.max(self.min_spacing)
+                    && *multiple < self.max_frequency
+                    && self.has_own_harmonics(*multiple, 2)
+                    && self.has_own_harmonics(*multiple, 3)
+            }) {
+                frequency = multiple;
+            }
+        }
+
+        // a sub-octave of the note (or an octave and a fifth below it) is missing the harmonics in between
+        'sub_harmonic: for _ in 0..2 {
+            for step in [2, 3] {
+                let above = frequency * step as f64;
+                if above < self.max_frequency && frequency > self.min_spacing && !self.has_own_harmonics(frequency, step) {
+                    frequency = above;
+                    continue 'sub_harmonic;
+                }
+            }
+            break;
+        }
+
+        // a harmonic of the note has a fundamental below it with harmonics of its own in between
+        for step in [2, 3] {
+            let below = frequency / step as f64;
+            if below > self.min_frequency.max(self.min_spacing) && self.has_own_harmonics(below, step) {
+                frequency = below;
+                break;
+            }
+        }
+
+        frequency
+    }
+
+    fn analyse(&mut self, signal: &[f64]) {
+        for (index, bin) in self.buffer.iter_mut().enumerate() {
+            let sample = match (signal.get(index), self.hann.get(index)) {
+                (Some(sample), Some(hann)) => sample * hann,
+                _ => 0.,
+            };
+            *bin = Complex::new(sample, 0.);
+        }
+        self.fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+
+        for (magnitude, bin) in self.spectrum.iter_mut().zip(&self.buffer) {
+            *magnitude = bin.norm();
+        }
+    }
+
+    // Whether `fundamental` has energy at its own harmonics, rather than only at every `step`th
+    // one (which would make it a sub-harmonic of the note `step` times higher)
+    fn has_own_harmonics(&self, fundamental: f64, step: usize) -> bool {
+        let (mut own, mut own_count) = (0., 0);
+        let (mut shared, mut shared_count) = (0., 0);
+
+        for harmonic in 1..=HARMONICS {
+            let frequency = fundamental * harmonic as f64;
+            if frequency > MAX_HARMONIC_FREQUENCY {
+                break;
+            }
+            let magnitude = self.peak_near(frequency);
+            if harmonic % step == 0 {
+                shared += magnitude;
+                shared_count += 1;
+            } else {
+                own += magnitude;
+                own_count += 1;
+            }
+        }
+        if own_count == 0 || shared_count == 0 {
+            return own > 0.;
+        }
+
+        // compare the average harmonic, there are more of its own than shared ones
+        let own = own / own_count as f64;
+        let shared = shared / shared_count as f64;
+        own > 0. && own >= shared * OWN_HARMONIC_RATIO
+    }
+
+    // Largest magnitude within a bin of `frequency`, the estimate is never spot on
+    fn peak_near(&self, frequency: f64) -> f64 {
+        let bin = frequency / (self.sample_rate as f64 / (self.spectrum.len() * 2) as f64);
+        let low = (bin.floor() as usize).saturating_sub(1);
+        let high = (bin.ceil() as usize + 1).min(self.spectrum.len() - 1);
+
+        self.spectrum.get(low..=high)
+            .map(|bins| bins.iter().copied().fold(0., f64::max))
+            .unwrap_or(0.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bass::note::midi_to_frequency;
+    use crate::bass::pitch_detector::playable_range;
+    use crate::bass::synth::{pluck, Noise};
+    use crate::bass::yin::YinDetector;
+
+    const SAMPLE_RATE: usize = 48000;
+    const WINDOW_SIZE: usize = 4096;
+    const LOW_E: f64 = 41.203_444_614;
+
+    fn corrector() -> OctaveCorrector {
+        let (min_frequency, max_frequency) = playable_range();
+        OctaveCorrector::new(WINDOW_SIZE, SAMPLE_RATE, min_frequency, max_frequency)
+    }
+
+    fn low_e() -> Vec<f64> {
+        pluck(LOW_E, SAMPLE_RATE, 0.02, WINDOW_SIZE, &mut Noise::default())
+    }
+
+    fn assert_low_e(frequency: f64) {
+        assert!((frequency - LOW_E).abs() < 0.5, "corrected to {} Hz", frequency);
+    }
+
+    #[test]
+    fn keeps_the_fundamental() {
+        assert_low_e(corrector().correct(&low_e(), LOW_E));
+    }
+
+    #[test]
+    fn sub_octave_goes_up_to_the_fundamental() {
+        assert_low_e(corrector().correct(&low_e(), LOW_E / 2.));
+        assert_low_e(corrector().correct(&low_e(), LOW_E / 3.));
+    }
+
+    #[test]
+    fn second_harmonic_goes_down_to_the_fundamental() {
+        assert_low_e(corrector().correct(&low_e(), LOW_E * 2.));
+    }
+
+    #[test]
+    fn keeps_a_note_an_octave_up() {
+        // E2 has no energy at E1, so it mustn't be taken for E1's second harmonic
+        let e2 = midi_to_frequency(40.);
+        let signal = pluck(e2, SAMPLE_RATE, 0.02, WINDOW_SIZE, &mut Noise::default());
+        let frequency = corrector().correct(&signal, e2);
+        assert!((frequency - e2).abs() < 0.5, "corrected to {} Hz", frequency);
+    }
+
+    #[test]
+    fn yin_and_corrector_read_low_e() {
+        let signal = low_e();
+        let (frequency, _) = YinDetector::new(WINDOW_SIZE, 0.15).get_pitch(&signal, SAMPLE_RATE).unwrap();
+        assert_low_e(corrector().correct(&signal, frequency));
+    }
+}
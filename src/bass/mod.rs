@@ -1 +1,10 @@
 pub mod pitch_detector;
+pub mod note;
+pub mod tuning;
+pub mod device;
+pub mod yin;
+pub mod benchmark;
+pub mod synth;
+pub mod source;
+pub mod harmonics;
+pub mod filter;
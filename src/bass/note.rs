@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+// A4 is the reference pitch everything in equal temperament is measured against
+pub const A4_FREQUENCY: f64 = 440.;
+pub const A4_MIDI: i32 = 69;
+
+// highest fret a chart can ask for
+pub const MAX_FRET: i8 = 24;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+// Cents tolerance a detected pitch has to fall within for a note to count as hit.
+// Lower values make the judgement stricter. Set from the difficulty when a level loads.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct HitTolerance(pub f64);
+
+impl Default for HitTolerance {
+    fn default() -> Self {
+        HitTolerance(50.)
+    }
+}
+
+// A detected pitch snapped to the nearest note of the equal tempered scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pitch {
+    pub midi: i32,
+    // how far the frequency sits from the nearest note (-50..50)
+    pub cents: f64,
+}
+
+impl Pitch {
+    pub fn from_frequency(frequency: f64) -> Option<Pitch> {
+        if frequency <= 0. || !frequency.is_finite() {
+            return None;
+        }
+        let midi = frequency_to_midi(frequency);
+        let nearest = midi.round();
+
+        Some(Pitch {
+            midi: nearest as i32,
+            cents: (midi - nearest) * 100.,
+        })
+    }
+
+    // e.g. "E1" for the open E string
+    pub fn name(&self) -> String {
+        note_name(self.midi)
+    }
+}
+
+// Fractional MIDI note number for a frequency (69.0 == A4 == 440 Hz)
+pub fn frequency_to_midi(frequency: f64) -> f64 {
+    A4_MIDI as f64 + 12. * (frequency / A4_FREQUENCY).log2()
+}
+
+pub fn midi_to_frequency(midi: f64) -> f64 {
+    A4_FREQUENCY * 2f64.powf((midi - A4_MIDI as f64) / 12.)
+}
+
+// Signed distance in cents from the expected note to a frequency
+pub fn cents_from_midi(frequency: f64, midi: i32) -> f64 {
+    (frequency_to_midi(frequency) - midi as f64) * 100.
+}
+
+pub fn note_name(midi: i32) -> String {
+    let octave = midi.div_euclid(12) - 1;
+    format!("{}{}", pitch_class_name(midi), octave)
+}
+
+// Name of a note without its octave, e.g. "D#"
+pub fn pitch_class_name(midi: i32) -> &'static str {
+    NOTE_NAMES[midi.rem_euclid(12) as usize]
+}
+
+// Parses scientific pitch notation ("E1", "Eb1", "F#2") into a MIDI note number
+pub fn parse_note_name(name: &str) -> Option<i32> {
+    let mut chars = name.trim().chars().peekable();
+    let letter = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let accidental = match chars.peek() {
+        Some('#') => 1,
+        Some('b') => -1,
+        _ => 0,
+    };
+    if accidental != 0 {
+        chars.next();
+    }
+    let octave: i32 = chars.collect::<String>().parse().ok()?;
+
+    Some((octave + 1) * 12 + letter + accidental)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} isn't {}", actual, expected);
+    }
+
+    #[test]
+    fn parse_note_name_reads_accidentals_and_octaves() {
+        assert_eq!(parse_note_name("E1"), Some(28));
+        assert_eq!(parse_note_name("Eb1"), Some(27));
+        assert_eq!(parse_note_name("F#2"), Some(42));
+        assert_eq!(parse_note_name(" e1 "), Some(28));
+        assert_eq!(parse_note_name("C-1"), Some(0));
+    }
+
+    #[test]
+    fn parse_note_name_rejects_invalid_names() {
+        assert_eq!(parse_note_name(""), None);
+        assert_eq!(parse_note_name("E"), None);
+        assert_eq!(parse_note_name("H1"), None);
+        assert_eq!(parse_note_name("E#"), None);
+        assert_eq!(parse_note_name("Ex1"), None);
+        assert_eq!(parse_note_name("E1.5"), None);
+    }
+
+    #[test]
+    fn note_names_round_trip() {
+        for midi in 12..=60 {
+            assert_eq!(parse_note_name(&note_name(midi)), Some(midi));
+        }
+    }
+
+    #[test]
+    fn frequency_to_midi_matches_equal_temperament() {
+        assert_close(frequency_to_midi(A4_FREQUENCY), 69.);
+        assert_close(frequency_to_midi(41.203_444_614), 28.);
+        assert_close(frequency_to_midi(55.), 33.);
+        assert_close(midi_to_frequency(frequency_to_midi(61.7)), 61.7);
+    }
+
+    #[test]
+    fn cents_from_midi_is_signed() {
+        assert_close(cents_from_midi(midi_to_frequency(28.25), 28), 25.);
+        assert_close(cents_from_midi(midi_to_frequency(27.9), 28), -10.);
+        assert_close(cents_from_midi(midi_to_frequency(29.), 28), 100.);
+    }
+
+    #[test]
+    fn pitch_rounds_to_the_nearest_note() {
+        let sharp = Pitch::from_frequency(midi_to_frequency(28.49)).unwrap();
+        assert_eq!(sharp.midi, 28);
+        assert_close(sharp.cents, 49.);
+
+        let flat = Pitch::from_frequency(midi_to_frequency(28.51)).unwrap();
+        assert_eq!(flat.midi, 29);
+        assert_close(flat.cents, -49.);
+        assert_eq!(flat.name(), "F1");
+    }
+
+    #[test]
+    fn pitch_needs_a_real_frequency() {
+        assert_eq!(Pitch::from_frequency(0.), None);
+        assert_eq!(Pitch::from_frequency(-41.2), None);
+        assert_eq!(Pitch::from_frequency(f64::NAN), None);
+    }
+}
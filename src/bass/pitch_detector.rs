@@ -1,64 +1,683 @@
+use bevy::app::AppExit;
 use bevy::prelude::*;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use bevy::utils::Instant;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use cpal::{BuildStreamError, Device, FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig, StreamError};
+use pitch_detection::detector::autocorrelation::AutocorrelationDetector;
 use pitch_detection::detector::mcleod::McLeodDetector;
 use pitch_detection::detector::PitchDetector;
+use serde::{Deserialize, Serialize};
 use ringbuf::*;
-use crossbeam_channel::{bounded, Receiver};
-use std::thread;
+use crossbeam_channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use std::error::Error;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::bass::device::find_input_device;
+use crate::bass::filter::PreFilter;
+use crate::bass::harmonics::OctaveCorrector;
+use crate::bass::source::InputSource;
+use crate::bass::note::{midi_to_frequency, MAX_FRET};
+use crate::bass::tuning::{LOWEST_STRING_MIDI, HIGHEST_STRING_MIDI};
+use crate::bass::yin::YinDetector;
+use crate::settings::{DetectorSettings, FilterSettings, GateSettings, Settings};
+
+// A pitch estimate from the detector thread
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PitchEvent {
+    pub frequency: f64,
+    // how periodic the window was according to the detector (0..1)
+    pub clarity: f64,
+    // RMS level of the analysed window, 1.0 being a full scale sine
+    pub rms: f64,
+    // roughly when the middle of the analysed window was played
+    pub time: Instant,
+}
+
+// A single pluck of a string. `time` is when the attack was heard and the pitch is the first
+// estimate taken once the detector window is filled with the new note.
+#[derive(Event, Debug, Clone, Copy, Deref)]
+pub struct PluckEvent(pub PitchEvent);
+
+// RMS level of the latest hop of input after the gain, for level meters
+#[derive(Event, Debug, Clone, Copy)]
+pub struct InputLevelEvent(pub f64);
+
+// The input device stopped working, most likely unplugged. Holds what went wrong.
+#[derive(Event, Debug, Clone)]
+pub struct DeviceLostEvent(pub String);
+
+// Everything the detector thread sends to the game
+pub enum DetectorMessage {
+    Pitch(PitchEvent),
+    Pluck(PitchEvent),
+    Level(f64),
+    DeviceLost(String),
+}
 
 #[derive(Resource, Deref)]
-pub struct StreamReceiver(Receiver<f64>);
+pub struct StreamReceiver(Receiver<DetectorMessage>);
+
+// What a pitch detection algorithm found in a window
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    pub frequency: f64,
+    pub clarity: f64,
+}
+
+// Common interface for the pitch detection algorithms so they can be swapped out in the settings.
+// Detectors are built on the thread that uses them, so they don't have to be Send. Quiet windows
+// are already kept away by the noise gate, windows less periodic than `clarity_threshold` (0..1)
+// aren't worth estimating.
+pub trait PitchAlgorithm {
+    fn estimate(&mut self, signal: &[f64], sample_rate: usize, clarity_threshold: f64) -> Option<Estimate>;
+}
+
+impl PitchAlgorithm for McLeodDetector<f64> {
+    fn estimate(&mut self, signal: &[f64], sample_rate: usize, clarity_threshold: f64) -> Option<Estimate> {
+        self.get_pitch(signal, sample_rate, 0., clarity_threshold)
+            .map(|pitch| Estimate { frequency: pitch.frequency, clarity: pitch.clarity })
+    }
+}
+
+impl PitchAlgorithm for AutocorrelationDetector<f64> {
+    fn estimate(&mut self, signal: &[f64], sample_rate: usize, clarity_threshold: f64) -> Option<Estimate> {
+        self.get_pitch(signal, sample_rate, 0., clarity_threshold)
+            .map(|pitch| Estimate { frequency: pitch.frequency, clarity: pitch.clarity })
+    }
+}
+
+impl PitchAlgorithm for YinDetector {
+    fn estimate(&mut self, signal: &[f64], sample_rate: usize, clarity_threshold: f64) -> Option<Estimate> {
+        self.get_pitch(signal, sample_rate)
+            .filter(|(_, clarity)| *clarity >= clarity_threshold)
+            .map(|(frequency, clarity)| Estimate { frequency, clarity })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetectorAlgorithm {
+    #[default]
+    McLeod,
+    Autocorrelation,
+    Yin,
+}
+
+impl DetectorAlgorithm {
+    pub const ALL: [DetectorAlgorithm; 3] = [DetectorAlgorithm::McLeod, DetectorAlgorithm::Autocorrelation, DetectorAlgorithm::Yin];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DetectorAlgorithm::McLeod => "McLeod",
+            DetectorAlgorithm::Autocorrelation => "Autocorrelation",
+            DetectorAlgorithm::Yin => "YIN",
+        }
+    }
+
+    pub fn build(&self, window_size: usize) -> Box<dyn PitchAlgorithm> {
+        match self {
+            DetectorAlgorithm::McLeod => Box::new(McLeodDetector::new(window_size, window_size / 2)),
+            DetectorAlgorithm::Autocorrelation => Box::new(AutocorrelationDetector::new(window_size, window_size / 2)),
+            DetectorAlgorithm::Yin => Box::new(YinDetector::new(window_size, 0.15)),
+        }
+    }
+}
+
+// a live input that hasn't sent anything for this long is taken to be gone, not every backend
+// reports an unplugged device as an error
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Keeps the audio thread alive. Dropping it stops the stream and waits for the thread to finish.
+#[derive(Resource)]
+pub struct AudioStream {
+    // the input device, or the file or test signal being played instead
+    pub device: String,
+    // None when every channel is mixed down
+    pub channel: Option<usize>,
+    gate_tx: Sender<GateSettings>,
+    shutdown_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AudioStream {
+    // Hands new gain and noise gate settings to the detector thread without reopening the stream
+    pub fn set_gate(&self, gate: &GateSettings) {
+        let _ = self.gate_tx.send(gate.clone());
+    }
+}
+
+impl Drop for AudioStream {
+    fn drop(&mut self) {
+        // the thread notices the closed channel within half a hop, and drops the cpal stream on its way out
+        self.shutdown_tx.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                println!("The audio thread panicked");
+            }
+        }
+    }
+}
+
+pub fn decibels_to_amplitude(decibels: f64) -> f64 {
+    10f64.powf(decibels / 20.)
+}
+
+pub fn amplitude_to_decibels(amplitude: f64) -> f64 {
+    20. * amplitude.max(1e-10).log10()
+}
+
+// Sent when the input settings change and the stream has to be reopened
+#[derive(Event)]
+pub struct RestartAudioEvent;
 
 pub fn read_audiostream(
     mut commands: Commands,
+    settings: Res<Settings>,
+    source: Res<InputSource>,
 ) {
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .expect("failed to find input device");
-    let config = device.default_input_config().unwrap();
-
-    let (tx, rx) = bounded::<f64>(1);
-    thread::spawn(move || {
-        // ring buffer initialization
-        let ring_buffer = HeapRb::<f32>::new(8192);
-        let (mut producer, mut consumer) = ring_buffer.split();
-
-        let err_fn = |err| println!("{}", err);
-        let stream = device
-            .build_input_stream(
-                &config.into(),
-                move |input, _| {
-                    const SAMPLE_RATE: usize = 44100;
-                    const SIZE: usize = 8192;
-                    const PADDING: usize = SIZE / 2;
-                    const POWER_THRESHOLD: f64 = 10.;
-                    const CLARITY_THRESHOLD: f64 = 0.6;
-                    
-                    if consumer.is_full() {
-                        let f64_vals: Vec<f64> = consumer.iter().map(|x| *x as f64).collect();
-                        let mut detector = McLeodDetector::new(SIZE, PADDING);
-                        if let Some(estimate) = detector.get_pitch(&f64_vals, SAMPLE_RATE, POWER_THRESHOLD, CLARITY_THRESHOLD) {
-                            // if out of bounds... don't count
-                            if estimate.frequency > 20. && estimate.frequency < 88. {
-                                tx.send(estimate.frequency).unwrap();
-                                println!("Estimated Frequency: {}", estimate.frequency);
-                            }
-                        }
-                        consumer.clear();
-                    } else {
-                        producer.push_slice(input);
-                    }
-                },
-                err_fn,
-                None,
-            )
-            .unwrap();
-
-        stream.play().unwrap();
-        loop {}
-    });
+    let (tx, rx) = unbounded::<DetectorMessage>();
+
+    let stream = match source.as_ref() {
+        InputSource::Device => spawn_stream(&settings, tx),
+        source => spawn_offline_stream(source, &settings, tx),
+    };
+    match stream {
+        Ok(stream) => {
+            println!("Reading Audio Stream from {}", stream.device);
+            commands.insert_resource(stream);
+        }
+        // the game still runs without an input, it just won't hear anything
+        Err(err) => {
+            println!("Couldn't open the input device: {}", err);
+            commands.remove_resource::<AudioStream>();
+        }
+    }
 
     commands.insert_resource(StreamReceiver(rx));
-    println!("Reading Audio Stream");
+}
+
+pub fn restart_audiostream(
+    commands: Commands,
+    settings: Res<Settings>,
+    source: Res<InputSource>,
+    mut restart_events: EventReader<RestartAudioEvent>,
+) {
+    if restart_events.iter().count() > 0 {
+        // replacing the AudioStream resource drops (and stops) the old stream
+        read_audiostream(commands, settings, source);
+    }
+}
+
+// Plays a file or test signal from the top when the level starts
+pub fn restart_offline_source(
+    source: Res<InputSource>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+) {
+    if source.is_offline() {
+        restart_audio.send(RestartAudioEvent);
+    }
+}
+
+// Stops the stream before the app exits rather than leaving the thread to be killed halfway
+// through a callback
+pub fn stop_audiostream(
+    mut commands: Commands,
+    mut exit_events: EventReader<AppExit>,
+) {
+    if exit_events.iter().count() > 0 {
+        commands.remove_resource::<AudioStream>();
+    }
+}
+
+// Hands everything the detector thread sent since the last frame to the rest of the game
+pub fn read_pitch_stream(
+    mut pitch_events: EventWriter<PitchEvent>,
+    mut pluck_events: EventWriter<PluckEvent>,
+    mut level_events: EventWriter<InputLevelEvent>,
+    mut device_lost_events: EventWriter<DeviceLostEvent>,
+    receiver: Res<StreamReceiver>,
+) {
+    for message in receiver.try_iter() {
+        match message {
+            DetectorMessage::Pitch(pitch) => pitch_events.send(pitch),
+            DetectorMessage::Pluck(pitch) => pluck_events.send(PluckEvent(pitch)),
+            DetectorMessage::Level(rms) => level_events.send(InputLevelEvent(rms)),
+            DetectorMessage::DeviceLost(reason) => device_lost_events.send(DeviceLostEvent(reason)),
+        }
+    }
+}
+
+fn spawn_stream(
+    settings: &Settings,
+    tx: Sender<DetectorMessage>,
+) -> Result<AudioStream, Box<dyn Error>> {
+    let input = &settings.input;
+    let device = find_input_device(input).ok_or("failed to find input device")?;
+    let device_name = device.name()?;
+    let supported_config = device.default_input_config()?;
+    let sample_format = supported_config.sample_format();
+    let config: StreamConfig = supported_config.into();
+
+    // mix every channel down unless one was picked (and it exists on this device)
+    let channels = config.channels as usize;
+    let channel = input.channel.filter(|channel| *channel < channels);
+    let sample_rate = config.sample_rate.0 as usize;
+
+    let detector_settings = settings.detector.clone();
+    let filter_settings = settings.filter.clone();
+    let gate = settings.gate(&device_name);
+    let (gate_tx, gate_rx) = unbounded::<GateSettings>();
+    let (shutdown_tx, shutdown_rx) = bounded::<()>(0);
+    let (ready_tx, ready_rx) = bounded::<Result<(), String>>(1);
+    let thread = thread::spawn(move || {
+        let analyser = PitchAnalyser::new(&detector_settings, &filter_settings, &gate, sample_rate, tx);
+
+        // ring buffer initialization, a second of audio is plenty of slack for the detector
+        let ring_buffer = HeapRb::<f32>::new(sample_rate.max(analyser.window.len()));
+        let (producer, consumer) = ring_buffer.split();
+
+        let lost_tx = analyser.tx.clone();
+        let stream = match sample_format {
+            SampleFormat::F32 => build_mono_stream::<f32>(&device, &config, channel, producer, lost_tx),
+            SampleFormat::I16 => build_mono_stream::<i16>(&device, &config, channel, producer, lost_tx),
+            SampleFormat::U16 => build_mono_stream::<u16>(&device, &config, channel, producer, lost_tx),
+            SampleFormat::I32 => build_mono_stream::<i32>(&device, &config, channel, producer, lost_tx),
+            format => {
+                let _ = ready_tx.send(Err(format!("unsupported sample format {}", format)));
+                return;
+            }
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                let _ = ready_tx.send(Err(err.to_string()));
+                return;
+            }
+        };
+        if let Err(err) = stream.play() {
+            let _ = ready_tx.send(Err(err.to_string()));
+            return;
+        }
+        let _ = ready_tx.send(Ok(()));
+
+        // the stream pushes into the ring buffer from its own callback
+        run_analyser(analyser, consumer, gate_rx, shutdown_rx, Some(STALL_TIMEOUT), || ());
+    });
+
+    ready_rx.recv()??;
+
+    Ok(AudioStream {
+        device: device_name,
+        channel,
+        gate_tx,
+        shutdown_tx: Some(shutdown_tx),
+        thread: Some(thread),
+    })
+}
+
+// Plays a WAV file or test signal through the detector as if it was coming in live
+fn spawn_offline_stream(
+    source: &InputSource,
+    settings: &Settings,
+    tx: Sender<DetectorMessage>,
+) -> Result<AudioStream, Box<dyn Error>> {
+    let (samples, sample_rate, looping) = source.load_samples()?;
+
+    let detector_settings = settings.detector.clone();
+    let filter_settings = settings.filter.clone();
+    let gate = settings.gate(&source.name());
+    let (gate_tx, gate_rx) = unbounded::<GateSettings>();
+    let (shutdown_tx, shutdown_rx) = bounded::<()>(0);
+    let thread = thread::spawn(move || {
+        let analyser = PitchAnalyser::new(&detector_settings, &filter_settings, &gate, sample_rate, tx);
+        let ring_buffer = HeapRb::<f32>::new(sample_rate.max(analyser.window.len()));
+        let (mut producer, consumer) = ring_buffer.split();
+
+        let started = Instant::now();
+        let mut position = 0;
+        run_analyser(analyser, consumer, gate_rx, shutdown_rx, None, || {
+            // push everything that would have been played by now, then silence once a file ends
+            let played = (started.elapsed().as_secs_f64() * sample_rate as f64) as usize;
+            position += producer.push_iter(&mut (position..played).map(|index| if looping {
+                samples[index % samples.len()]
+            } else {
+                samples.get(index).copied().unwrap_or(0.)
+            }));
+        });
+    });
+
+    Ok(AudioStream {
+        device: source.name(),
+        channel: None,
+        gate_tx,
+        shutdown_tx: Some(shutdown_tx),
+        thread: Some(thread),
+    })
+}
+
+// Slides the window along one hop at a time until the AudioStream resource is dropped. `feed` is
+// called every time the thread wakes up, for sources that don't push samples in by themselves.
+// With a `stall_timeout` the device is reported lost once no samples arrive for that long.
+fn run_analyser(
+    mut analyser: PitchAnalyser,
+    mut consumer: HeapConsumer<f32>,
+    gate_rx: Receiver<GateSettings>,
+    shutdown_rx: Receiver<()>,
+    stall_timeout: Option<Duration>,
+    mut feed: impl FnMut(),
+) {
+    let mut hop = vec![0f32; analyser.hop_size];
+    let hop_duration = Duration::from_secs_f64(analyser.hop_size as f64 / analyser.sample_rate as f64);
+    let mut last_samples = Instant::now();
+    let mut stalled = false;
+
+    while let Err(RecvTimeoutError::Timeout) = shutdown_rx.recv_timeout(hop_duration / 2) {
+        for gate in gate_rx.try_iter() {
+            analyser.set_gate(&gate);
+        }
+        feed();
+        if !consumer.is_empty() {
+            last_samples = Instant::now();
+        }
+        while consumer.len() >= hop.len() {
+            consumer.pop_slice(&mut hop);
+            analyser.process(&hop, consumer.len());
+        }
+
+        if stall_timeout.is_some_and(|timeout| last_samples.elapsed() > timeout) && !stalled {
+            stalled = true;
+            let _ = analyser.tx.send(DetectorMessage::DeviceLost("the input stopped sending audio".to_string()));
+        }
+    }
+}
+
+// Opens the device with its own sample type and pushes mono f32 samples into the ring buffer
+fn build_mono_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    channel: Option<usize>,
+    mut producer: HeapProducer<f32>,
+    lost_tx: Sender<DetectorMessage>,
+) -> Result<Stream, BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let err_fn = move |err| match err {
+        StreamError::DeviceNotAvailable => {
+            let _ = lost_tx.send(DetectorMessage::DeviceLost(err.to_string()));
+        }
+        err => println!("{}", err),
+    };
+
+    device.build_input_stream(
+        config,
+        move |input: &[T], _: &cpal::InputCallbackInfo| {
+            // either keep the chosen channel or average all of them out of each interleaved frame
+            producer.push_iter(&mut input.chunks(channels).map(|frame| match channel {
+                Some(channel) => f32::from_sample(frame[channel]),
+                None => frame.iter().map(|sample| f32::from_sample(*sample)).sum::<f32>() / channels as f32,
+            }));
+        },
+        err_fn,
+        None,
+    )
+}
+
+// Anything a six string bass can play with a semitone of headroom either side
+pub fn playable_range() -> (f64, f64) {
+    (
+        midi_to_frequency((LOWEST_STRING_MIDI - 1) as f64),
+        midi_to_frequency((HIGHEST_STRING_MIDI + MAX_FRET as i32 + 1) as f64),
+    )
+}
+
+// Runs the pitch and onset detectors over a sliding window of mono samples. Everything it works
+// with is allocated once up front.
+struct PitchAnalyser {
+    pre_filter: PreFilter,
+    detector: Box<dyn PitchAlgorithm>,
+    octave_corrector: Option<OctaveCorrector>,
+    onset_detector: OnsetDetector,
+    window: Vec<f64>,
+    // the latest hop, filtered and with the gain applied
+    hop: Vec<f32>,
+    sample_rate: usize,
+    hop_size: usize,
+    min_frequency: f64,
+    max_frequency: f64,
+    gain: f64,
+    // RMS a window needs to be estimated at all
+    noise_gate: f64,
+    clarity_threshold: f64,
+    // when the last onset was heard and how many samples have arrived since
+    pending_pluck: Option<(Instant, usize)>,
+    tx: Sender<DetectorMessage>,
+}
+
+impl PitchAnalyser {
+    fn new(
+        settings: &DetectorSettings,
+        filter: &FilterSettings,
+        gate: &GateSettings,
+        sample_rate: usize,
+        tx: Sender<DetectorMessage>,
+    ) -> PitchAnalyser {
+        let window_size = settings.window_size.max(1024);
+        let hop_size = settings.hop_size.clamp(1, window_size);
+        let (min_frequency, max_frequency) = playable_range();
+
+        let mut analyser = PitchAnalyser {
+            pre_filter: PreFilter::new(filter, sample_rate, min_frequency),
+            detector: settings.algorithm.build(window_size),
+            octave_corrector: settings.octave_correction
+                .then(|| OctaveCorrector::new(window_size, sample_rate, min_frequency, max_frequency)),
+            onset_detector: OnsetDetector::new(sample_rate, hop_size),
+            window: vec![0.; window_size],
+            hop: vec![0.; hop_size],
+            sample_rate,
+            hop_size,
+            min_frequency,
+            max_frequency,
+            gain: 1.,
+            noise_gate: 0.,
+            clarity_threshold: 0.,
+            pending_pluck: None,
+            tx,
+        };
+        analyser.set_gate(gate);
+
+        analyser
+    }
+
+    fn set_gate(&mut self, gate: &GateSettings) {
+        self.gain = gate.gain();
+        self.noise_gate = gate.noise_gate();
+        self.clarity_threshold = gate.clarity;
+        // a pluck has to clear the gate too
+        self.onset_detector.min_rms = self.noise_gate;
+    }
+
+    // `queued` is how many samples are still waiting behind this hop, used to work out how long
+    // ago it was played
+    fn process(&mut self, hop: &[f32], queued: usize) {
+        self.hop.copy_from_slice(hop);
+        self.pre_filter.process(&mut self.hop);
+        for sample in self.hop.iter_mut() {
+            *sample *= self.gain as f32;
+        }
+        let hop_rms = (self.hop.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / self.hop_size as f64).sqrt();
+        let _ = self.tx.send(DetectorMessage::Level(hop_rms));
+
+        let window_size = self.window.len();
+        self.window.copy_within(self.hop_size.., 0);
+        for (sample, new_sample) in self.window[window_size - self.hop_size..].iter_mut().zip(&self.hop) {
+            *sample = *new_sample as f64;
+        }
+
+        let now = Instant::now();
+        let sample_rate = self.sample_rate;
+        let samples_ago = |samples: usize| now - Duration::from_secs_f64(samples as f64 / sample_rate as f64);
+
+        if self.onset_detector.process(&self.hop) {
+            self.pending_pluck = Some((samples_ago(queued + self.hop_size / 2), 0));
+        } else if let Some((_, since_onset)) = self.pending_pluck.as_mut() {
+            *since_onset += self.hop_size;
+        }
+        // give up on plucks that never settle on a pitch, like a muted string or a knock on the
+        // body, before they can be matched to whatever's played next
+        if self.pending_pluck.is_some_and(|(_, since_onset)| since_onset > window_size * 2) {
+            self.pending_pluck = None;
+        }
+
+        // string noise and hum under the gate isn't worth estimating
+        let rms = (self.window.iter().map(|sample| sample * sample).sum::<f64>() / window_size as f64).sqrt();
+        if rms < self.noise_gate {
+            return;
+        }
+
+        let Some(mut estimate) = self.detector.estimate(&self.window, self.sample_rate, self.clarity_threshold) else {
+            return;
+        };
+        if let Some(corrector) = self.octave_corrector.as_mut() {
+            estimate.frequency = corrector.correct(&self.window, estimate.frequency);
+        }
+        // if out of bounds... don't count
+        if estimate.frequency <= self.min_frequency || estimate.frequency >= self.max_frequency {
+            return;
+        }
+
+        let pitch = PitchEvent {
+            frequency: estimate.frequency,
+            clarity: estimate.clarity,
+            rms,
+            // the middle of the window is everything still queued up plus half a window ago
+            time: samples_ago(queued + window_size / 2),
+        };
+        let _ = self.tx.send(DetectorMessage::Pitch(pitch));
+
+        // wait until at least half the window has been played since the onset, otherwise the
+        // estimate could still be the previous note ringing out
+        if let Some((onset, since_onset)) = self.pending_pluck {
+            if since_onset >= window_size / 2 {
+                let _ = self.tx.send(DetectorMessage::Pluck(PitchEvent { time: onset, ..pitch }));
+                self.pending_pluck = None;
+            }
+        }
+    }
+}
+
+// Picks out the attack of a plucked string from the level of each hop. An onset is a jump of
+// ONSET_RATIO over the recent level that clears `min_rms`, and every onset is followed by a short
+// hold off so one pluck can't trigger twice.
+pub struct OnsetDetector {
+    pub min_rms: f64,
+    envelope: f64,
+    // how much of the previous envelope is kept each hop
+    smoothing: f64,
+    hold_off_hops: usize,
+    hold_off: usize,
+}
+
+impl OnsetDetector {
+    const ONSET_RATIO: f64 = 1.6;
+    const ENVELOPE_SECONDS: f64 = 0.05;
+    const HOLD_OFF_SECONDS: f64 = 0.05;
+
+    pub fn new(sample_rate: usize, hop_size: usize) -> OnsetDetector {
+        let hop_seconds = hop_size as f64 / sample_rate as f64;
+
+        OnsetDetector {
+            min_rms: 0.,
+            envelope: 0.,
+            smoothing: (-hop_seconds / Self::ENVELOPE_SECONDS).exp(),
+            hold_off_hops: (Self::HOLD_OFF_SECONDS / hop_seconds).ceil() as usize,
+            hold_off: 0,
+        }
+    }
+
+    // returns true if the hop holds the attack of a new note
+    pub fn process(&mut self, hop: &[f32]) -> bool {
+        let rms = (hop.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / hop.len() as f64).sqrt();
+
+        let onset = self.hold_off == 0 && rms > self.min_rms && rms > self.envelope * Self::ONSET_RATIO;
+        if onset {
+            self.hold_off = self.hold_off_hops;
+        } else {
+            self.hold_off = self.hold_off.saturating_sub(1);
+        }
+        self.envelope = self.envelope * self.smoothing + rms * (1. - self.smoothing);
+        // a low note's hops are shorter than its period and swing a lot in level, so the envelope
+        // jumps straight up to the attack rather than still catching up with it once the hold off ends
+        if self.hold_off > 0 {
+            self.envelope = self.envelope.max(rms);
+        }
+
+        onset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bass::note::cents_from_midi;
+    use crate::bass::synth::{pluck, Noise};
+
+    const SAMPLE_RATE: usize = 48000;
+    const HOP_SIZE: usize = 512;
+    const LOW_E_MIDI: i32 = 28;
+
+    // `count` plucks of the same note, each left to ring for `ring` seconds and then muted for a
+    // moment before the next
+    fn repeated_plucks(midi: i32, count: usize, ring: f64) -> Vec<f32> {
+        let mut noise = Noise::default();
+        let ring = (ring * SAMPLE_RATE as f64) as usize;
+        let rest = vec![0.; SAMPLE_RATE / 5];
+
+        let mut signal = rest.clone();
+        for _ in 0..count {
+            let note = pluck(midi_to_frequency(midi as f64), SAMPLE_RATE, 0., ring, &mut noise);
+            signal.extend(note.into_iter().map(|sample| sample as f32));
+            signal.extend(&rest);
+        }
+        signal
+    }
+
+    fn count_onsets(signal: &[f32]) -> usize {
+        let mut detector = OnsetDetector::new(SAMPLE_RATE, HOP_SIZE);
+        detector.min_rms = decibels_to_amplitude(-40.);
+
+        signal.chunks_exact(HOP_SIZE).filter(|hop| detector.process(hop)).count()
+    }
+
+    #[test]
+    fn one_onset_per_pluck() {
+        assert_eq!(count_onsets(&repeated_plucks(LOW_E_MIDI, 4, 0.6)), 4);
+        assert_eq!(count_onsets(&repeated_plucks(LOW_E_MIDI + 12, 4, 0.6)), 4);
+    }
+
+    #[test]
+    fn ringing_note_has_no_more_onsets() {
+        assert_eq!(count_onsets(&repeated_plucks(LOW_E_MIDI, 1, 3.)), 1);
+    }
+
+    #[test]
+    fn analyser_sends_one_pluck_per_pluck_at_its_pitch() {
+        let (tx, rx) = unbounded();
+        let mut analyser = PitchAnalyser::new(&DetectorSettings::default(), &FilterSettings::default(), &GateSettings::default(), SAMPLE_RATE, tx);
+        for hop in repeated_plucks(LOW_E_MIDI, 4, 0.6).chunks_exact(HOP_SIZE) {
+            analyser.process(hop, 0);
+        }
+
+        let plucks: Vec<f64> = rx.try_iter()
+            .filter_map(|message| match message {
+                DetectorMessage::Pluck(pluck) => Some(pluck.frequency),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(plucks.len(), 4);
+        for frequency in plucks {
+            assert!(cents_from_midi(frequency, LOW_E_MIDI).abs() < 50., "heard {} Hz", frequency);
+        }
+    }
 }
@@ -0,0 +1,112 @@
+use bevy::prelude::*;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use crate::bass::note::{midi_to_frequency, parse_note_name};
+use crate::bass::synth::{pluck, Noise};
+use crate::bass::tuning::Tuning;
+
+const TEST_SIGNAL_SAMPLE_RATE: usize = 48000;
+// how long each note of the test signal rings before the next is plucked
+const TEST_SIGNAL_NOTE_SECONDS: f64 = 1.;
+
+// Where the pitch detector gets its audio from. A live input unless one of these is given on the
+// command line:
+//   --input-wav <file>            plays a recording through the detector in real time
+//   --input-test-signal [notes]   plucks the comma separated notes (E1,A1,D2,...) over and over,
+//                                 the open strings of standard tuning by default
+// Offline sources start over whenever a level starts, so a take recorded along with a chart
+// lines up with it.
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub enum InputSource {
+    #[default]
+    Device,
+    WavFile(PathBuf),
+    TestSignal(Vec<i32>),
+}
+
+impl InputSource {
+    pub fn from_args() -> Result<InputSource, Box<dyn Error>> {
+        let args: Vec<String> = std::env::args().collect();
+
+        if let Some(index) = args.iter().position(|arg| arg == "--input-wav") {
+            let path = args.get(index + 1).ok_or("--input-wav needs the path of a WAV file")?;
+            return Ok(InputSource::WavFile(PathBuf::from(path)));
+        }
+
+        if let Some(index) = args.iter().position(|arg| arg == "--input-test-signal") {
+            let notes = match args.get(index + 1).filter(|arg| !arg.starts_with("--")) {
+                Some(notes) => notes.split(',')
+                    .map(|name| parse_note_name(name.trim()).ok_or_else(|| format!("{} is not a note name", name)))
+                    .collect::<Result<Vec<i32>, String>>()?,
+                None => Tuning::standard().strings.iter().map(|string| string.midi).collect(),
+            };
+            return Ok(InputSource::TestSignal(notes));
+        }
+
+        Ok(InputSource::Device)
+    }
+
+    pub fn is_offline(&self) -> bool {
+        *self != InputSource::Device
+    }
+
+    // What to call the source in the settings screen
+    pub fn name(&self) -> String {
+        match self {
+            InputSource::Device => "Input device".to_string(),
+            InputSource::WavFile(path) => path.file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+            InputSource::TestSignal(_) => "Test signal".to_string(),
+        }
+    }
+
+    // The mono samples of an offline source, their sample rate and whether they should loop
+    pub fn load_samples(&self) -> Result<(Vec<f32>, usize, bool), Box<dyn Error>> {
+        match self {
+            InputSource::Device => Err("a live input has no samples to load".into()),
+            InputSource::WavFile(path) => {
+                let (samples, sample_rate) = read_wav(path)?;
+                Ok((samples, sample_rate, false))
+            }
+            InputSource::TestSignal(notes) => Ok((test_signal(notes), TEST_SIGNAL_SAMPLE_RATE, true)),
+        }
+    }
+}
+
+// Reads a WAV file of any sample format, mixing every channel down
+fn read_wav(path: &Path) -> Result<(Vec<f32>, usize), Box<dyn Error>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    if samples.is_empty() {
+        return Err(format!("{} has no audio in it", path.display()).into());
+    }
+
+    let mono = samples.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok((mono, spec.sample_rate as usize))
+}
+
+// Every note plucked once, one after the other
+fn test_signal(notes: &[i32]) -> Vec<f32> {
+    let note_length = (TEST_SIGNAL_NOTE_SECONDS * TEST_SIGNAL_SAMPLE_RATE as f64) as usize;
+    let mut noise = Noise::default();
+
+    notes.iter()
+        .flat_map(|midi| pluck(midi_to_frequency(*midi as f64), TEST_SIGNAL_SAMPLE_RATE, 0., note_length, &mut noise))
+        .map(|sample| sample as f32)
+        .collect()
+}
@@ -0,0 +1,66 @@
+use std::f64::consts::TAU;
+
+// relative level of each harmonic, a plucked bass has a weaker fundamental than second harmonic
+const HARMONICS: [f64; 8] = [0.6, 1.0, 0.5, 0.35, 0.25, 0.15, 0.1, 0.05];
+// higher harmonics die out faster than the fundamental
+const DECAY_SECONDS: f64 = 1.5;
+// white noise about 30 dB under the note
+const NOISE_LEVEL: f64 = 0.03;
+const PEAK_LEVEL: f64 = 0.5;
+const CLICK_FREQUENCY: f64 = 1500.;
+const CLICK_SECONDS: f64 = 0.05;
+const CLICK_DECAY_SECONDS: f64 = 0.008;
+
+// A rough plucked bass string: a decaying stack of harmonics with some noise on top. Returns
+// `length` samples starting `start` seconds after the pluck.
+pub fn pluck(frequency: f64, sample_rate: usize, start: f64, length: usize, noise: &mut Noise) -> Vec<f64> {
+    let total: f64 = HARMONICS.iter().sum();
+    // start each harmonic at its own phase so the wave isn't unnaturally symmetric
+    let phases: Vec<f64> = HARMONICS.iter().map(|_| noise.sample() * TAU).collect();
+
+    (0..length)
+        .map(|sample| {
+            let time = start + sample as f64 / sample_rate as f64;
+            let tone: f64 = HARMONICS.iter().zip(&phases).enumerate()
+                .map(|(index, (amplitude, phase))| {
+                    let harmonic = (index + 1) as f64;
+                    let decay = (-time * harmonic / DECAY_SECONDS).exp();
+                    amplitude * decay * (TAU * frequency * harmonic * time + phase).sin()
+                })
+                .sum();
+            PEAK_LEVEL * tone / total + NOISE_LEVEL * (noise.sample() * 2. - 1.)
+        })
+        .collect()
+}
+
+// A short metronome tick, a high sine that dies away within a few milliseconds so its start is
+// unmistakable
+pub fn click(sample_rate: usize) -> Vec<f64> {
+    let length = (CLICK_SECONDS * sample_rate as f64) as usize;
+
+    (0..length)
+        .map(|sample| {
+            let time = sample as f64 / sample_rate as f64;
+            PEAK_LEVEL * (-time / CLICK_DECAY_SECONDS).exp() * (TAU * CLICK_FREQUENCY * time).sin()
+        })
+        .collect()
+}
+
+// xorshift, so every run synthesizes exactly the same signal without pulling in a rand crate
+pub struct Noise(u64);
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise(0x2545F4914F6CDD1D)
+    }
+}
+
+impl Noise {
+    // uniform in 0..1
+    pub fn sample(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
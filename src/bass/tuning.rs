@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::bass::note::{parse_note_name, MAX_FRET};
+
+// range of open strings we support, from the low B of a five string up to the high C of a six
+pub const LOWEST_STRING_MIDI: i32 = 23;
+pub const HIGHEST_STRING_MIDI: i32 = 48;
+
+// names of the presets, in the order the settings screen goes through them
+pub const PRESETS: [&str; 7] = ["Standard", "Drop D", "Half Step Down", "BEAD", "Five String", "Five String High C", "Six String"];
+
+// A single string of the bass. `name` is the label charts use for the string, which is its
+// position on a standard tuned bass (drop D still calls its lowest string "E"), and `midi`
+// is the pitch the open string is actually tuned to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TunedString {
+    pub name: String,
+    pub midi: i32,
+}
+
+// Strings are ordered from lowest to highest
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub name: String,
+    pub strings: Vec<TunedString>,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning::standard()
+    }
+}
+
+impl Tuning {
+    fn from_strings(name: &str, strings: &[(&str, i32)]) -> Tuning {
+        Tuning {
+            name: name.to_string(),
+            strings: strings.iter().map(|(string, midi)| TunedString {
+                name: string.to_string(),
+                midi: *midi,
+            }).collect(),
+        }
+    }
+
+    pub fn standard() -> Tuning {
+        Tuning::from_strings("Standard", &[("E", 28), ("A", 33), ("D", 38), ("G", 43)])
+    }
+
+    pub fn drop_d() -> Tuning {
+        Tuning::from_strings("Drop D", &[("E", 26), ("A", 33), ("D", 38), ("G", 43)])
+    }
+
+    pub fn half_step_down() -> Tuning {
+        Tuning::from_strings("Half Step Down", &[("E", 27), ("A", 32), ("D", 37), ("G", 42)])
+    }
+
+    // standard tuning shifted down a fourth, so the strings are the bottom four of a five string
+    pub fn bead() -> Tuning {
+        Tuning::from_strings("BEAD", &[("B", 23), ("E", 28), ("A", 33), ("D", 38)])
+    }
+
+    pub fn five_string() -> Tuning {
+        Tuning::from_strings("Five String", &[("B", 23), ("E", 28), ("A", 33), ("D", 38), ("G", 43)])
+    }
+
+    pub fn five_string_high_c() -> Tuning {
+        Tuning::from_strings("Five String High C", &[("E", 28), ("A", 33), ("D", 38), ("G", 43), ("C", 48)])
+    }
+
+    pub fn six_string() -> Tuning {
+        Tuning::from_strings("Six String", &[("B", 23), ("E", 28), ("A", 33), ("D", 38), ("G", 43), ("C", 48)])
+    }
+
+    pub fn preset(name: &str) -> Option<Tuning> {
+        match name.to_lowercase().as_str() {
+            "standard" | "eadg" => Some(Tuning::standard()),
+            "drop d" | "dadg" => Some(Tuning::drop_d()),
+            "half step down" | "eb" => Some(Tuning::half_step_down()),
+            "bead" => Some(Tuning::bead()),
+            "five string" | "beadg" => Some(Tuning::five_string()),
+            "five string high c" | "eadgc" => Some(Tuning::five_string_high_c()),
+            "six string" | "beadgc" => Some(Tuning::six_string()),
+            _ => None,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> Option<&TunedString> {
+        self.strings.iter().find(|string| string.name == name)
+    }
+
+    // Expected pitch of a fretted note, None if the string or fret doesn't exist
+    pub fn fret_midi(&self, string: &str, fret: i8) -> Option<i32> {
+        if !(0..=MAX_FRET).contains(&fret) {
+            return None;
+        }
+        self.string(string).map(|string| string.midi + fret as i32)
+    }
+}
+
+// How a chart declares its tuning. Either the name of a preset:
+//     "Tuning": "Drop D"
+// or every string spelled out from lowest to highest:
+//     "Tuning": [{ "String": "E", "Note": "D1" }, { "String": "A", "Note": "A1" }, ...]
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TuningJson {
+    Preset(String),
+    Custom(Vec<TunedStringJson>),
+}
+
+#[allow(non_snake_case)]
+#[derive(Deserialize, Debug)]
+pub struct TunedStringJson {
+    String: String,
+    Note: String,
+}
+
+impl TuningJson {
+    pub fn to_tuning(&self) -> Result<Tuning, Box<dyn Error>> {
+        match self {
+            TuningJson::Preset(name) => Tuning::preset(name)
+                .ok_or_else(|| format!("Unknown tuning preset \"{}\"", name).into()),
+            TuningJson::Custom(strings) => {
+                let mut tuning = Tuning {
+                    name: "Custom".to_string(),
+                    strings: Vec::new(),
+                };
+                for string in strings {
+                    let midi = parse_note_name(&string.Note)
+                        .ok_or_else(|| format!("Invalid note \"{}\" for string {}", string.Note, string.String))?;
+                    if !(LOWEST_STRING_MIDI..=HIGHEST_STRING_MIDI).contains(&midi) {
+                        return Err(format!("String {} can't be tuned to {}", string.String, string.Note).into());
+                    }
+                    if tuning.string(&string.String).is_some() {
+                        return Err(format!("String {} is tuned more than once", string.String).into());
+                    }
+                    tuning.strings.push(TunedString {
+                        name: string.String.clone(),
+                        midi,
+                    });
+                }
+
+                Ok(tuning)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<Tuning, Box<dyn Error>> {
+        serde_json::from_str::<TuningJson>(json).unwrap().to_tuning()
+    }
+
+    #[test]
+    fn presets_are_found_by_name() {
+        assert_eq!(parse(r#""Drop D""#).unwrap(), Tuning::drop_d());
+        assert_eq!(parse(r#""beadg""#).unwrap(), Tuning::five_string());
+        assert!(parse(r#""Drop C#""#).is_err());
+    }
+
+    #[test]
+    fn custom_tunings_read_each_string() {
+        let tuning = parse(r#"[{"String": "E", "Note": "D1"}, {"String": "A", "Note": "Ab1"}]"#).unwrap();
+        assert_eq!(tuning.name, "Custom");
+        assert_eq!(tuning.string("E").map(|string| string.midi), Some(26));
+        assert_eq!(tuning.string("A").map(|string| string.midi), Some(32));
+    }
+
+    #[test]
+    fn custom_tunings_stay_in_range() {
+        assert!(parse(r#"[{"String": "B", "Note": "B0"}]"#).is_ok());
+        assert!(parse(r#"[{"String": "B", "Note": "Bb0"}]"#).is_err());
+        assert!(parse(r#"[{"String": "C", "Note": "C3"}]"#).is_ok());
+        assert!(parse(r#"[{"String": "C", "Note": "C#3"}]"#).is_err());
+        assert!(parse(r#"[{"String": "E", "Note": "low E"}]"#).is_err());
+    }
+
+    #[test]
+    fn custom_tunings_tune_each_string_once() {
+        assert!(parse(r#"[{"String": "E", "Note": "E1"}, {"String": "E", "Note": "D1"}]"#).is_err());
+    }
+
+    #[test]
+    fn fret_midi_only_covers_the_neck() {
+        let tuning = Tuning::standard();
+        assert_eq!(tuning.fret_midi("E", 0), Some(28));
+        assert_eq!(tuning.fret_midi("G", MAX_FRET), Some(43 + MAX_FRET as i32));
+        assert_eq!(tuning.fret_midi("G", MAX_FRET + 1), None);
+        assert_eq!(tuning.fret_midi("E", -1), None);
+        assert_eq!(tuning.fret_midi("B", 0), None);
+    }
+}
@@ -0,0 +1,103 @@
+// YIN pitch detection (de Cheveigné & Kawahara, 2002). The pitch_detection crate only has
+// McLeod and autocorrelation so this is our own.
+pub struct YinDetector {
+    // cumulative mean normalized difference for every lag up to half the window
+    difference: Vec<f64>,
+    threshold: f64,
+}
+
+impl YinDetector {
+    pub fn new(size: usize, threshold: f64) -> YinDetector {
+        YinDetector {
+            difference: vec![0.; size / 2],
+            threshold,
+        }
+    }
+
+    // returns the frequency and how clear the period was (1 - the normalized difference at it)
+    pub fn get_pitch(&mut self, signal: &[f64], sample_rate: usize) -> Option<(f64, f64)> {
+        let max_lag = self.difference.len().min(signal.len() / 2);
+        if max_lag < 3 {
+            return None;
+        }
+
+        // difference function
+        for lag in 1..max_lag {
+            self.difference[lag] = signal[..signal.len() - max_lag].iter()
+                .zip(&signal[lag..signal.len() - max_lag + lag])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum();
+        }
+
+        // cumulative mean normalization
+        self.difference[0] = 1.;
+        let mut running_sum = 0.;
+        for lag in 1..max_lag {
+            running_sum += self.difference[lag];
+            self.difference[lag] = if running_sum > 0. {
+                self.difference[lag] * lag as f64 / running_sum
+            } else {
+                1.
+            };
+        }
+
+        // first dip under the threshold, followed down to its local minimum
+        let mut lag = 2;
+        while lag < max_lag - 1 {
+            if self.difference[lag] < self.threshold {
+                while lag + 1 < max_lag - 1 && self.difference[lag + 1] < self.difference[lag] {
+                    lag += 1;
+                }
+                break;
+            }
+            lag += 1;
+        }
+        if lag >= max_lag - 1 {
+            return None;
+        }
+
+        // parabolic interpolation for a lag between samples
+        let (before, at, after) = (self.difference[lag - 1], self.difference[lag], self.difference[lag + 1]);
+        let denominator = before + after - 2. * at;
+        let shift = if denominator.abs() > f64::EPSILON { (before - after) / (2. * denominator) } else { 0. };
+
+        Some((sample_rate as f64 / (lag as f64 + shift), (1. - at).clamp(0., 1.)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bass::note::{cents_from_midi, midi_to_frequency};
+    use crate::bass::synth::{pluck, Noise};
+
+    const SAMPLE_RATE: usize = 48000;
+    const WINDOW_SIZE: usize = 4096;
+
+    fn detect(midi: i32) -> (f64, f64) {
+        let signal = pluck(midi_to_frequency(midi as f64), SAMPLE_RATE, 0.02, WINDOW_SIZE, &mut Noise::default());
+        YinDetector::new(WINDOW_SIZE, 0.15).get_pitch(&signal, SAMPLE_RATE).unwrap()
+    }
+
+    #[test]
+    fn reads_low_e_at_its_fundamental() {
+        let (frequency, clarity) = detect(28);
+        assert!((frequency - 41.2).abs() < 0.5, "heard {} Hz", frequency);
+        assert!(clarity > 0.8);
+    }
+
+    #[test]
+    fn reads_bass_notes_within_a_few_cents() {
+        // B0 on a five string up to the 12th fret of the G string
+        for midi in [23, 28, 33, 38, 43, 55] {
+            let (frequency, _) = detect(midi);
+            assert!(cents_from_midi(frequency, midi).abs() < 10., "heard {} Hz for midi {}", frequency, midi);
+        }
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        let mut detector = YinDetector::new(WINDOW_SIZE, 0.15);
+        assert_eq!(detector.get_pitch(&[0.; WINDOW_SIZE], SAMPLE_RATE), None);
+    }
+}
@@ -1,9 +1,41 @@
 use bevy::prelude::*;
-use std::collections::HashMap;
-use crate::{GameState, bass::pitch_detector::StreamReceiver, LevelScore, CurrentBassNote};
+use bevy::utils::Instant;
+use crate::{GameState, DueNotes, IntroTimer, LevelClock};
+use crate::bass::pitch_detector::PluckEvent;
+use crate::bass::note::{HitTolerance, cents_from_midi};
+use crate::bass::tuning::Tuning;
+use crate::settings::Settings;
 
-#[derive(Event)]
-pub struct BassInput(bool);
+// how long after a pluck is heard its event can still be on its way from the detector thread,
+// which waits for half a window of the new note before estimating its pitch
+const PLUCK_ARRIVAL_SECONDS: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Grade {
+    Perfect,
+    Great,
+    Good,
+    Miss,
+}
+
+impl Grade {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grade::Perfect => "Perfect",
+            Grade::Great => "Great",
+            Grade::Good => "Good",
+            Grade::Miss => "Miss",
+        }
+    }
+}
+
+// How a note was judged. `offset` is how many seconds late (or early, when negative) it was
+// played, None when it went by without being played.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct JudgementEvent {
+    pub grade: Grade,
+    pub offset: Option<f64>,
+}
 
 pub fn state_inputs(
     input: Res<Input<KeyCode>>,
@@ -17,112 +49,98 @@ pub fn state_inputs(
     } 
 }
 
-pub fn read_input_stream(
-    mut input_events: EventWriter<BassInput>,
-    mut current_note: ResMut<CurrentBassNote>,
-    receiver: Res<StreamReceiver>,
+// Judge pitch as strictly as the difficulty being played asks
+pub fn apply_hit_tolerance(
+    mut tolerance: ResMut<HitTolerance>,
+    settings: Res<Settings>,
 ) {
-    // hashmap containing chord to frequency corresponding values
-    let freq_to_note: HashMap<&str, [(f64, f64); 11]> = HashMap::from([
-        ("E", [
-            (20., 21.),
-            (22., 23.),
-            (23., 24.),
-            (24., 25.),
-            (26., 27.),
-            (27., 28.),
-            (29., 30.),
-            (31., 32.),
-            (32., 33.),
-            (34., 36.),
-            (36., 38.),
-        ]),
-        ("A", [
-            (26., 28.),
-            (28., 30.),
-            (30., 32.),
-            (32., 34.),
-            (34., 36.),
-            (36., 38.),
-            (38., 40.),
-            (40., 42.),
-            (42., 45.),
-            (45., 47.),
-            (47., 49.),
-        ]),
-        ("D", [
-            (36., 37.),
-            (39., 40.),
-            (41., 42.),
-            (43., 44.),
-            (46., 47.),
-            (49., 50.),
-            (52., 53.),
-            (55., 56.),
-            (58., 59.),
-            (61., 63.),
-            (65., 66.),
-        ]),
-        ("G", [
-            (48., 50.),
-            (52., 53.),
-            (54., 55.5),
-            (58., 59.),
-            (61., 62.),
-            (65., 66.),
-            (69., 70.),
-            (73., 74.),
-            (77., 78.),
-            (82., 83.),
-            (87., 88.),
-        ]),
-    ]);
-
-    for estimate in receiver.try_iter() {
-        let freq_bounds = freq_to_note.get(current_note.chord.as_str()).unwrap_or(&[
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-            (0., 0.),
-        ]);
-        if (current_note.fret < 11 && current_note.fret > -1) && estimate >= freq_bounds[current_note.fret as usize].0 && estimate <= freq_bounds[current_note.fret as usize].1 {
-            println!("Chord -> {}, Fret -> {}", current_note.chord, current_note.fret);
-            input_events.send(BassInput(true));
-        } else {
-            input_events.send(BassInput(false));
-        }
+    tolerance.0 = settings.scoring_rules().hit_tolerance_cents;
+}
 
-        *current_note = CurrentBassNote {
-            chord: "".to_string(),
-            fret: -1,
-        };
-    }
+// A pluck with when it was heard, in seconds of the song like a DueNote
+#[derive(Clone, Copy)]
+pub struct HeardPluck {
+    time: f64,
+    frequency: f64,
 }
 
-pub fn print_if_true(
-    mut correct_events: EventReader<BassInput>,
-    mut score: ResMut<LevelScore>,
+// Matches plucks to the notes that came due around when they were heard. A pluck up to the Good
+// window early or late plays the note if it's the right pitch, and the closer it was the better
+// the grade. A note nothing played by the end of its window is a miss.
+//
+// Everything is timed on the level clock so the time spent paused doesn't count against notes
+// that were coming due, or plucks heard just before pausing.
+#[allow(clippy::too_many_arguments)]
+pub fn read_input_stream(
+    mut judgement_events: EventWriter<JudgementEvent>,
+    mut due_notes: ResMut<DueNotes>,
+    mut pluck_events: EventReader<PluckEvent>,
+    mut plucks: Local<Vec<HeardPluck>>,
+    clock: Res<LevelClock>,
+    intro_timer: Res<IntroTimer>,
+    tolerance: Res<HitTolerance>,
+    tuning: Res<Tuning>,
+    settings: Res<Settings>,
 ) {
-    for events in correct_events.iter() {
-        if events.0 {
-            score.0 += 25;
-        } else {
-            score.0 -= 10;
+    let windows = &settings.judgement;
+    let good_window = windows.good_ms / 1000.;
+    // the song starts playing once the intro's over
+    let song_time = clock.0.elapsed_seconds_f64() - intro_timer.0.duration().as_secs_f64();
+    let clock_updated = clock.0.last_update().unwrap_or_else(Instant::now);
+    // plucks are held on to for a while as one can be heard before its note comes due. They're
+    // only ever a moment old when they arrive, so count back from the clock's last update.
+    plucks.extend(pluck_events.iter().map(|pluck| HeardPluck {
+        time: song_time - signed_seconds(clock_updated, pluck.0.time),
+        frequency: pluck.0.frequency,
+    }));
+
+    let mut index = 0;
+    while index < due_notes.0.len() {
+        let note = &due_notes.0[index];
+        // compare against the equal tempered pitch of the note, less however far off the
+        // calibration found the player's bass reads on that string
+        let offset_cents = settings.string_offset(&tuning, note.chord.as_str(), note.fret);
+        let expected = tuning.fret_midi(note.chord.as_str(), note.fret);
+        let timing = |pluck: &HeardPluck| pluck.time - note.due;
+
+        let closest = plucks.iter().enumerate()
+            .filter(|(_, pluck)| timing(pluck).abs() <= good_window)
+            .filter(|(_, pluck)| expected.is_some_and(|midi| (cents_from_midi(pluck.frequency, midi) - offset_cents).abs() <= tolerance.0))
+            .min_by(|(_, a), (_, b)| timing(a).abs().total_cmp(&timing(b).abs()))
+            .map(|(pluck_index, pluck)| (pluck_index, timing(pluck)));
+
+        match closest {
+            Some((pluck_index, offset)) => {
+                debug!("Chord -> {}, Fret -> {}, {:+.0} ms", note.chord, note.fret, offset * 1000.);
+                plucks.remove(pluck_index);
+                due_notes.0.remove(index);
+                judgement_events.send(JudgementEvent {
+                    grade: windows.grade(offset),
+                    offset: Some(offset),
+                });
+            }
+            None => index += 1,
         }
-        println!("Score -> {}", score.0);
     }
-    correct_events.clear();
+
+    // past the window with nothing played, the note can't be hit any more
+    due_notes.0.retain(|note| {
+        let pending = song_time < note.due + good_window + PLUCK_ARRIVAL_SECONDS;
+        if !pending {
+            debug!("Missed Chord -> {}, Fret -> {}", note.chord, note.fret);
+            judgement_events.send(JudgementEvent {
+                grade: Grade::Miss,
+                offset: None,
+            });
+        }
+        pending
+    });
+    // a pluck too old for any note still to come due was a wrong note or a stray one, the note it
+    // was meant for is missed on its own
+    plucks.retain(|pluck| song_time < pluck.time + good_window);
 }
 
-pub fn reset_score(
-    mut score: ResMut<LevelScore>,
-) {
-    score.0 = 0;
+// Seconds from `expected` to `time`, negative when `time` came first
+fn signed_seconds(time: Instant, expected: Instant) -> f64 {
+    time.saturating_duration_since(expected).as_secs_f64() - expected.saturating_duration_since(time).as_secs_f64()
 }
@@ -150,6 +150,16 @@ pub fn update_level_clock(
     clock.0.update();
 }
 
+// Only time spent playing counts on the level clock. Bring it up to now while it's still paused so
+// loading, the tuning check and the pause menu are skipped over.
+pub fn resume_level_clock(
+    mut clock: ResMut<LevelClock>,
+) {
+    clock.0.pause();
+    clock.0.update();
+    clock.0.unpause();
+}
+
 pub fn pause_level_clock(
     mut clock: ResMut<LevelClock>,
 ) {
@@ -6,8 +6,13 @@ mod bass;
 mod song;
 mod ui;
 mod animations;
+mod settings;
+mod scoring;
+mod save_data;
+mod profile;
 
 use bevy::prelude::*;
+use std::collections::VecDeque;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_asset_loader::prelude::*;
 use bevy::render::RenderPlugin;
@@ -26,8 +31,25 @@ use crate::ui::menu::*;
 use crate::ui::bass_ui::*;
 use crate::ui::load_screen::*;
 use crate::ui::score::*;
+use crate::ui::settings::*;
+use crate::ui::device_lost::*;
+use crate::ui::calibration::*;
+use crate::ui::tuner::*;
+use crate::ui::tuning_check::*;
+use crate::ui::latency::*;
+use crate::ui::results::*;
+use crate::ui::profiles::*;
 use crate::song::*;
 use crate::bass::pitch_detector::*;
+use crate::bass::note::HitTolerance;
+use crate::bass::tuning::Tuning;
+use crate::bass::device::list_devices;
+use crate::bass::benchmark::run_benchmark;
+use crate::bass::source::InputSource;
+use crate::settings::*;
+use crate::scoring::*;
+use crate::save_data::*;
+use crate::profile::*;
 
 // States for game status
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, States)]
@@ -40,20 +62,30 @@ pub enum GameState {
     MenuAssetLoading,
     AssetLoading,
     AssetsLoaded,
+    Settings,
+    StringCalibration,
+    Tuner,
+    TuningCheck,
+    LatencyTest,
+    Results,
+    NewProfile,
 }
 
 #[derive(Component)]
 pub struct WorldCamera;
 
-#[derive(Resource)]
-pub struct LevelScore(i32);
-
-#[derive(Resource)]
-pub struct CurrentBassNote {
+// A note of the chart waiting for the pluck that plays it. `due` is when that pluck should be
+// heard, in seconds on the level clock from when the song starts.
+pub struct DueNote {
     chord: String,
     fret: i8,
+    due: f64,
 }
 
+// Oldest first
+#[derive(Resource, Default)]
+pub struct DueNotes(VecDeque<DueNote>);
+
 fn setup(mut commands: Commands) {
     commands.spawn((
         Camera2dBundle {
@@ -94,6 +126,29 @@ fn set_window_icon(
 }
 
 fn main() {
+    // print the inputs the pitch detector can listen to and exit
+    if std::env::args().any(|arg| arg == "--list-devices") {
+        list_devices();
+        return;
+    }
+    // compare the pitch detection algorithms on synthesized notes and exit
+    if std::env::args().any(|arg| arg == "--benchmark-detectors") {
+        run_benchmark();
+        return;
+    }
+    // a WAV file or test signal can stand in for the bass
+    let input_source = match InputSource::from_args() {
+        Ok(source) => source,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    // everything saved is kept per profile, starting with the one picked last time
+    let profiles = Profiles::load();
+    let profile = profiles.active_profile();
+
     App::new()
         .add_plugins(DefaultPlugins.set(RenderPlugin {
             wgpu_settings: WgpuSettings {
@@ -109,17 +164,52 @@ fn main() {
         }))
         .add_plugins((LdtkPlugin, BellyPlugin, AsepritePlugin))
         .insert_resource(LevelSelection::Index(0))
-        .insert_resource(LevelScore(0))
-        .insert_resource(CurrentBassNote {
-            chord: "".to_string(),
-            fret: -1,
-        })
+        .init_resource::<LevelScore>()
+        .init_resource::<HitTolerance>()
+        .init_resource::<Tuning>()
+        .insert_resource(Settings::load(&profile))
+        .insert_resource(SaveData::load(&profile))
+        .insert_resource(profile)
+        .insert_resource(profiles)
+        .insert_resource(input_source)
+        .init_resource::<DueNotes>()
+        .init_resource::<LevelStats>()
         .add_event::<PlayEvent>()
         .add_event::<QuitEvent>()
         .add_event::<ExitLevelEvent>()
         .add_event::<WorldEvent>()
         .add_event::<NoteCollision>()
-        .add_event::<BassInput>()
+        .add_event::<JudgementEvent>()
+        .add_event::<SettingsEvent>()
+        .add_event::<BackEvent>()
+        .add_event::<InputSelectEvent>()
+        .add_event::<AlgorithmSelectEvent>()
+        .add_event::<GainEvent>()
+        .add_event::<NoiseGateEvent>()
+        .add_event::<AutoGainEvent>()
+        .add_event::<HumFilterEvent>()
+        .add_event::<DcBlockerEvent>()
+        .add_event::<BandPassEvent>()
+        .add_event::<StringCalibrationEvent>()
+        .add_event::<SkipNoteEvent>()
+        .add_event::<RestartCalibrationEvent>()
+        .add_event::<TunerEvent>()
+        .add_event::<TuningCheckToggleEvent>()
+        .add_event::<DifficultyEvent>()
+        .add_event::<SkipTuningCheckEvent>()
+        .add_event::<LatencyTestEvent>()
+        .add_event::<RestartLatencyTestEvent>()
+        .add_event::<ProfileSelectEvent>()
+        .add_event::<NewProfileEvent>()
+        .add_event::<CreateProfileEvent>()
+        .add_event::<TuningSelectEvent>()
+        .add_event::<RetryEvent>()
+        .add_event::<ResultsBackEvent>()
+        .add_event::<RestartAudioEvent>()
+        .add_event::<PitchEvent>()
+        .add_event::<PluckEvent>()
+        .add_event::<InputLevelEvent>()
+        .add_event::<DeviceLostEvent>()
         // main menu state management
         .add_state::<GameState>()
         .add_state::<LevelState>()
@@ -134,8 +224,31 @@ fn main() {
         )
         .add_systems(OnExit(GameState::MenuAssetLoading), draw_main_menu_ui)
         // MainMenu Systems
-        .add_systems(Update, (close_event, insert_world_dir).run_if(in_state(GameState::MainMenu)))
+        .add_systems(OnEnter(GameState::MainMenu), apply_profile_tuning)
+        .add_systems(Update, (close_event, insert_world_dir, settings_event, tuner_event, new_profile_event).run_if(in_state(GameState::MainMenu)))
+        .add_systems(Update, profile_select_event.before(restart_audiostream).run_if(in_state(GameState::MainMenu)))
         .add_systems(OnExit(GameState::MainMenu), despawn_ui)
+        // New profile systems
+        .add_systems(OnEnter(GameState::NewProfile), (start_new_profile, draw_new_profile_ui, spawn_new_profile_name))
+        .add_systems(Update, (back_event, type_profile_name, create_profile_event.before(restart_audiostream), update_new_profile_name).chain().run_if(in_state(GameState::NewProfile)))
+        .add_systems(OnExit(GameState::NewProfile), (despawn_ui, despawn_new_profile_name, end_new_profile))
+        // Settings systems
+        .add_systems(OnEnter(GameState::Settings), (draw_settings_ui, spawn_input_status, spawn_input_meter))
+        .add_systems(Update, (back_event, input_select_event, algorithm_select_event, update_input_status).run_if(in_state(GameState::Settings)))
+        .add_systems(Update, (gate_event, filter_event, auto_gain_event, update_gain_calibration, update_input_meter, string_calibration_event, tuning_check_toggle_event, difficulty_event, tuning_select_event, latency_test_event).run_if(in_state(GameState::Settings)))
+        .add_systems(OnExit(GameState::Settings), (despawn_ui, despawn_input_status, despawn_input_meter, end_gain_calibration))
+        // String calibration systems
+        .add_systems(OnEnter(GameState::StringCalibration), (start_string_calibration, draw_string_calibration_ui, spawn_calibration_status))
+        .add_systems(Update, (calibration_back_event, update_string_calibration, update_calibration_status).chain().run_if(in_state(GameState::StringCalibration)))
+        .add_systems(OnExit(GameState::StringCalibration), (despawn_ui, despawn_calibration_status, end_string_calibration))
+        // Latency test systems
+        .add_systems(OnEnter(GameState::LatencyTest), (start_latency_test, draw_latency_test_ui, spawn_latency_test_status))
+        .add_systems(Update, (calibration_back_event, update_latency_test, update_latency_test_status).chain().run_if(in_state(GameState::LatencyTest)))
+        .add_systems(OnExit(GameState::LatencyTest), (despawn_ui, despawn_latency_test_status, end_latency_test))
+        // Tuner systems
+        .add_systems(OnEnter(GameState::Tuner), (draw_tuner_ui, spawn_tuner))
+        .add_systems(Update, (back_event, update_tuner).run_if(in_state(GameState::Tuner)))
+        .add_systems(OnExit(GameState::Tuner), (despawn_ui, despawn_tuner))
         // Asset loading state that continues to pre-level systems
         .add_loading_state(
             LoadingState::new(GameState::AssetLoading)
@@ -149,33 +262,43 @@ fn main() {
         .add_collection_to_loading_state::<_, VerticalBassStrumAsset>(GameState::AssetLoading)
         .add_collection_to_loading_state::<_, BassStringAsset>(GameState::AssetLoading)
         .add_collection_to_loading_state::<_, BassNoteAssets>(GameState::AssetLoading)
-        .add_collection_to_loading_state::<_, FretNumberAssets>(GameState::AssetLoading)
         // systems to spawn assets into the world
-        .add_systems(OnEnter(GameState::AssetLoading), (insert_level_metadata, spawn_load_screen))
-        .add_systems(OnExit(GameState::AssetLoading), (spawn_score, spawn_music, spawn_bass_ui, spawn_character, load_world))
+        .add_systems(OnEnter(GameState::AssetLoading), (insert_level_metadata, spawn_load_screen, reset_score, apply_hit_tolerance))
+        .add_systems(OnExit(GameState::AssetLoading), (spawn_score, spawn_judgement, spawn_music, spawn_bass_ui, spawn_character, load_world))
         // all systems for pre-level start
         .add_systems(OnEnter(GameState::AssetsLoaded), set_player_bounds)
         .add_systems(Update, exit_load_screen.run_if(in_state(GameState::AssetsLoaded)))
-        .add_systems(OnExit(GameState::AssetsLoaded), (fit_camera_to_window, spawn_bass_notes, insert_beat_coords))
+        .add_systems(OnExit(GameState::AssetsLoaded), (fit_camera_to_window, spawn_bass_notes, schedule_due_notes, insert_beat_coords, restart_offline_source))
+        // tuning check between loading and playing the level
+        .add_systems(OnEnter(GameState::TuningCheck), (start_tuning_check, draw_tuning_check_ui, spawn_tuning_check_status))
+        .add_systems(Update, (skip_tuning_check_event, update_tuning_check, update_tuning_check_status).chain().run_if(in_state(GameState::TuningCheck)))
+        .add_systems(OnExit(GameState::TuningCheck), (despawn_ui, despawn_tuning_check_status, end_tuning_check))
         // MainMenu systems
         /* .add_systems(OnEnter(GameState::MainMenu), (level_start, spawn_menu_world))
         .add_systems(Update, (fit_camera_to_window, handle_level_camera_translations).run_if(in_state(GameState::MainMenu)))
         .add_systems(OnExit(GameState::MainMenu), (despawn_character, despawn_world)) */
         // InGame systems
-        .add_systems(OnEnter(GameState::InGame), (level_start, unpause_game_clock))
+        .add_systems(OnEnter(GameState::InGame), (level_start, unpause_game_clock, resume_level_clock))
         // run these systems before movement
         .add_systems(Update, (manage_level_states).before(handle_level_camera_translations).run_if(in_state(GameState::InGame)))
-        .add_systems(Update, (game_state_end, read_input_stream, player_movement, print_if_true, write_note_collision).run_if(in_state(GameState::InGame)))
-        .add_systems(Update, (animations::toggle_portal_animation, update_score, translate_bass_notes, update_level_clock).run_if(in_state(GameState::InGame)))
+        .add_systems(Update, (game_state_end, read_input_stream.after(update_level_clock), player_movement, score_judgements, write_note_collision).run_if(in_state(GameState::InGame)))
+        .add_systems(Update, (animations::toggle_portal_animation, update_score, update_judgement, flash_pick_on_miss, translate_bass_notes, update_level_clock).run_if(in_state(GameState::InGame)))
         .add_systems(OnExit(GameState::InGame), (pause_level_clock, pause_game_clock))
         // GameState::Paused 
         .add_systems(OnEnter(GameState::Paused), (draw_game_menu_ui, pause_song))
         .add_systems(Update, (exit_level_event, play_event).run_if(in_state(GameState::Paused)))
         .add_systems(OnExit(GameState::Paused), despawn_ui)
         // GameState::Ending
-        .add_systems(OnEnter(GameState::Ending), (despawn_clock_time, despawn_world, despawn_character, despawn_bass_ui, despawn_music, reset_camera, level_exit, reset_score, despawn_score).before(load_main_menu))
-        .add_systems(Update, (load_main_menu).run_if(in_state(GameState::Ending)))
+        .add_systems(OnEnter(GameState::Ending), (miss_remaining_notes, despawn_clock_time, despawn_world, despawn_character, despawn_bass_ui, despawn_music, reset_camera, level_exit, despawn_score, despawn_judgement).before(load_results))
+        .add_systems(Update, (load_results).run_if(in_state(GameState::Ending)))
+        // GameState::Results
+        .add_systems(OnEnter(GameState::Results), (record_result, draw_results_ui).chain())
+        .add_systems(Update, (retry_event, results_back_event).run_if(in_state(GameState::Results)))
+        .add_systems(OnExit(GameState::Results), despawn_ui)
         .add_systems(Startup, (set_window_icon, setup, read_audiostream))
-        .add_systems(Update, state_inputs)
+        .add_systems(Update, (state_inputs, restart_audiostream, read_pitch_stream))
+        // audio device loss and reconnection
+        .add_systems(Update, (reconnect_audiostream, device_lost_event, update_device_lost_message).chain().after(read_pitch_stream))
+        .add_systems(Last, stop_audiostream)
         .run();
 }
@@ -0,0 +1,164 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use crate::settings::data_dir;
+
+// the profile made the first time the game runs, which takes over anything saved before there
+// were profiles
+const DEFAULT_PROFILE: &str = "Player";
+const MAX_NAME_LENGTH: usize = 24;
+// what used to be saved straight into the data directory
+const UNPROFILED_FILES: [&str; 2] = ["settings.json", "save_data.json"];
+
+// The player whose settings and results are in use. Everything of theirs is saved in their own
+// directory.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+}
+
+impl Profile {
+    pub fn dir(&self) -> PathBuf {
+        data_dir().join("profiles").join(&self.name)
+    }
+}
+
+// Every profile on this machine and the one that was last picked
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Profiles {
+    pub names: Vec<String>,
+    pub active: String,
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Profiles {
+            names: vec![DEFAULT_PROFILE.to_string()],
+            active: DEFAULT_PROFILE.to_string(),
+        }
+    }
+}
+
+impl Profiles {
+    // The last picked profile, or the first one if it's gone missing from the list
+    pub fn active_profile(&self) -> Profile {
+        let name = if self.names.contains(&self.active) {
+            self.active.clone()
+        } else {
+            self.names.first().cloned().unwrap_or_else(|| DEFAULT_PROFILE.to_string())
+        };
+        Profile { name }
+    }
+
+    // Names become directory names, so only letters, numbers, spaces, - and _ are allowed
+    pub fn add(&mut self, name: &str) -> Result<Profile, Box<dyn Error>> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("a profile needs a name".into());
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!("profile names can be at most {} characters", MAX_NAME_LENGTH).into());
+        }
+        if !name.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_') {
+            return Err("profile names can only have letters, numbers, spaces, - and _".into());
+        }
+        if self.names.iter().any(|existing| existing.to_lowercase() == name.to_lowercase()) {
+            return Err(format!("there's already a profile called {}", name).into());
+        }
+
+        self.names.push(name.to_string());
+        Ok(Profile { name: name.to_string() })
+    }
+
+    fn path() -> PathBuf {
+        data_dir().join("profiles.json")
+    }
+
+    // The first time, make the default profile and move whatever was saved before profiles into it
+    pub fn load() -> Profiles {
+        let path = Profiles::path();
+        if !path.exists() {
+            let profiles = Profiles::default();
+            if let Err(err) = profiles.adopt_unprofiled_files() {
+                println!("Couldn't move old saves into the {} profile: {}", DEFAULT_PROFILE, err);
+            }
+            return profiles;
+        }
+
+        match Profiles::read(&path) {
+            Ok(profiles) => profiles,
+            Err(err) => {
+                println!("Couldn't read {}: {}", path.display(), err);
+                Profiles::default()
+            }
+        }
+    }
+
+    fn adopt_unprofiled_files(&self) -> Result<(), Box<dyn Error>> {
+        let dir = self.active_profile().dir();
+        for file in UNPROFILED_FILES {
+            let old = data_dir().join(file);
+            if old.exists() {
+                fs::create_dir_all(&dir)?;
+                fs::rename(&old, dir.join(file))?;
+            }
+        }
+        self.save()
+    }
+
+    fn read(path: &Path) -> Result<Profiles, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = Profiles::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_trims_the_name() {
+        let mut profiles = Profiles::default();
+        let profile = profiles.add("  Sam ").unwrap();
+        assert_eq!(profile.name, "Sam");
+        assert_eq!(profiles.names, vec![DEFAULT_PROFILE, "Sam"]);
+    }
+
+    #[test]
+    fn add_rejects_names_that_cant_be_directories() {
+        let mut profiles = Profiles::default();
+        assert!(profiles.add("").is_err());
+        assert!(profiles.add("   ").is_err());
+        assert!(profiles.add("../Sam").is_err());
+        assert!(profiles.add("Sam.Bass").is_err());
+        assert!(profiles.add(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert!(profiles.add(&"a".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert_eq!(profiles.names.len(), 2);
+    }
+
+    #[test]
+    fn add_rejects_names_differing_only_in_case() {
+        let mut profiles = Profiles::default();
+        assert!(profiles.add(&DEFAULT_PROFILE.to_uppercase()).is_err());
+        profiles.add("Élise").unwrap();
+        assert!(profiles.add("élise").is_err());
+        assert_eq!(profiles.names, vec![DEFAULT_PROFILE, "Élise"]);
+    }
+}
@@ -0,0 +1,212 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::input::Grade;
+use crate::level::LevelResource;
+use crate::profile::Profile;
+use crate::scoring::{LevelScore, LevelStats};
+use crate::settings::{Difficulty, Settings};
+
+// Set when the player quits a level from the pause menu, a level that wasn't played to the end
+// skips the results and isn't recorded
+#[derive(Resource)]
+pub struct LevelQuit;
+
+// One play of a chart to the end
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attempt {
+    // seconds since the Unix epoch
+    pub timestamp: u64,
+    pub difficulty: Difficulty,
+    pub score: i32,
+    pub accuracy: f64,
+    pub max_streak: usize,
+    pub perfect: usize,
+    pub great: usize,
+    pub good: usize,
+    pub misses: usize,
+}
+
+// Everything kept about one chart, the bests are over every difficulty
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SongRecord {
+    pub best_score: i32,
+    pub best_accuracy: f64,
+    pub plays: usize,
+    // oldest first
+    pub history: Vec<Attempt>,
+}
+
+impl SongRecord {
+    fn record(&mut self, attempt: Attempt) {
+        if self.plays == 0 || attempt.score > self.best_score {
+            self.best_score = attempt.score;
+        }
+        if self.plays == 0 || attempt.accuracy > self.best_accuracy {
+            self.best_accuracy = attempt.accuracy;
+        }
+        self.plays += 1;
+        self.history.push(attempt);
+    }
+
+    // Whether the latest attempt beat the score of every one before it. The first play of a chart
+    // has nothing to beat so it isn't one.
+    pub fn new_best_score(&self) -> bool {
+        match self.history.split_last() {
+            Some((latest, earlier)) if !earlier.is_empty() => earlier.iter().all(|attempt| latest.score > attempt.score),
+            _ => false,
+        }
+    }
+
+    pub fn new_best_accuracy(&self) -> bool {
+        match self.history.split_last() {
+            Some((latest, earlier)) if !earlier.is_empty() => earlier.iter().all(|attempt| latest.accuracy > attempt.accuracy),
+            _ => false,
+        }
+    }
+}
+
+// Results that should survive a restart, saved alongside the profile's settings
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SaveData {
+    // keyed by the chart's level path, as in LevelResource
+    pub songs: HashMap<String, SongRecord>,
+}
+
+impl SaveData {
+    pub fn song(&self, level: &Path) -> Option<&SongRecord> {
+        self.songs.get(&SaveData::key(level))
+    }
+
+    fn key(level: &Path) -> String {
+        level.to_string_lossy().to_string()
+    }
+
+    fn path(profile: &Profile) -> PathBuf {
+        profile.dir().join("save_data.json")
+    }
+
+    // Falls back on no results if there's nothing saved yet or the file can't be read. An unreadable
+    // file is moved aside to save_data.json.bak first so the next save doesn't write over it.
+    pub fn load(profile: &Profile) -> SaveData {
+        let path = SaveData::path(profile);
+        if !path.exists() {
+            return SaveData::default();
+        }
+
+        match SaveData::read(&path) {
+            Ok(save_data) => save_data,
+            Err(err) => {
+                println!("Couldn't read {}: {}", path.display(), err);
+                let backup = path.with_file_name("save_data.json.bak");
+                match fs::rename(&path, &backup) {
+                    Ok(()) => println!("Moved it to {}", backup.display()),
+                    Err(err) => println!("Couldn't move it to {}: {}", backup.display(), err),
+                }
+                SaveData::default()
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<SaveData, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, profile: &Profile) -> Result<(), Box<dyn Error>> {
+        let path = SaveData::path(profile);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+
+        Ok(())
+    }
+}
+
+// Record the level that just ended before the results are drawn, so they can show if it was a
+// new best
+pub fn record_result(
+    mut save_data: ResMut<SaveData>,
+    level: Res<LevelResource>,
+    score: Res<LevelScore>,
+    stats: Res<LevelStats>,
+    settings: Res<Settings>,
+    profile: Res<Profile>,
+) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let attempt = Attempt {
+        timestamp,
+        difficulty: settings.difficulty,
+        score: score.points,
+        accuracy: stats.accuracy(),
+        max_streak: score.max_streak,
+        perfect: stats.count(Grade::Perfect),
+        great: stats.count(Grade::Great),
+        good: stats.count(Grade::Good),
+        misses: stats.count(Grade::Miss),
+    };
+    save_data.songs.entry(SaveData::key(&level.0)).or_default().record(attempt);
+
+    if let Err(err) = save_data.save(&profile) {
+        println!("Couldn't save results: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(score: i32, accuracy: f64) -> Attempt {
+        Attempt {
+            timestamp: 0,
+            difficulty: Difficulty::Normal,
+            score,
+            accuracy,
+            max_streak: 0,
+            perfect: 0,
+            great: 0,
+            good: 0,
+            misses: 0,
+        }
+    }
+
+    #[test]
+    fn first_play_is_not_a_new_best() {
+        let mut song = SongRecord::default();
+        song.record(attempt(100, 50.));
+        assert_eq!(song.best_score, 100);
+        assert!(!song.new_best_score());
+        assert!(!song.new_best_accuracy());
+    }
+
+    #[test]
+    fn new_best_has_to_beat_every_earlier_play() {
+        let mut song = SongRecord::default();
+        song.record(attempt(300, 80.));
+        song.record(attempt(200, 90.));
+        assert!(!song.new_best_score());
+        assert!(song.new_best_accuracy());
+
+        // matching the best isn't beating it
+        song.record(attempt(300, 90.));
+        assert!(!song.new_best_score());
+        assert!(!song.new_best_accuracy());
+
+        song.record(attempt(301, 10.));
+        assert!(song.new_best_score());
+        assert_eq!(song.best_score, 301);
+        assert_eq!(song.best_accuracy, 90.);
+        assert_eq!(song.plays, 4);
+    }
+}
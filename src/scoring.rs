@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::DueNotes;
+use crate::input::{Grade, JudgementEvent};
+use crate::settings::{ScoringRules, Settings};
+
+// The score of the level being played and the streak of notes hit in a row building it
+#[derive(Resource, Debug)]
+pub struct LevelScore {
+    pub points: i32,
+    pub streak: usize,
+    pub max_streak: usize,
+    pub multiplier: i32,
+}
+
+impl Default for LevelScore {
+    fn default() -> Self {
+        LevelScore {
+            points: 0,
+            streak: 0,
+            max_streak: 0,
+            multiplier: 1,
+        }
+    }
+}
+
+impl LevelScore {
+    // Score one judged note. The note that reaches the next multiplier already scores with it.
+    pub fn add(&mut self, grade: Grade, rules: &ScoringRules) {
+        if rules.keeps_streak(grade) {
+            self.streak += 1;
+            self.max_streak = self.max_streak.max(self.streak);
+        } else {
+            self.streak = 0;
+        }
+        self.multiplier = rules.multiplier(self.streak);
+
+        self.points += match grade {
+            // a miss costs the same whatever the streak was
+            Grade::Miss => rules.points(grade),
+            _ => rules.points(grade) * self.multiplier,
+        };
+    }
+}
+
+// How many notes of the level got each grade so far
+#[derive(Resource, Debug, Default)]
+pub struct LevelStats {
+    pub grades: HashMap<Grade, usize>,
+}
+
+impl LevelStats {
+    pub fn count(&self, grade: Grade) -> usize {
+        self.grades.get(&grade).copied().unwrap_or(0)
+    }
+
+    pub fn notes(&self) -> usize {
+        self.grades.values().sum()
+    }
+
+    // How close to every note being Perfect the level was, 0 to 100. Great and Good count for
+    // part of a note.
+    pub fn accuracy(&self) -> f64 {
+        if self.notes() == 0 {
+            return 0.;
+        }
+        let credit = self.count(Grade::Perfect) as f64
+            + self.count(Grade::Great) as f64 * 0.75
+            + self.count(Grade::Good) as f64 * 0.5;
+
+        credit / self.notes() as f64 * 100.
+    }
+
+    // Letter for the accuracy, S being (nearly) all Perfect
+    pub fn letter_grade(&self) -> &'static str {
+        match self.accuracy() {
+            accuracy if accuracy >= 95. => "S",
+            accuracy if accuracy >= 90. => "A",
+            accuracy if accuracy >= 80. => "B",
+            accuracy if accuracy >= 70. => "C",
+            accuracy if accuracy >= 60. => "D",
+            _ => "F",
+        }
+    }
+}
+
+pub fn score_judgements(
+    mut judgement_events: EventReader<JudgementEvent>,
+    mut score: ResMut<LevelScore>,
+    mut stats: ResMut<LevelStats>,
+    settings: Res<Settings>,
+) {
+    let rules = settings.scoring_rules();
+    for judgement in judgement_events.iter() {
+        *stats.grades.entry(judgement.grade).or_default() += 1;
+        score.add(judgement.grade, &rules);
+        debug!("{} -> Score {}, Streak {}, {}x", judgement.grade.name(), score.points, score.streak, score.multiplier);
+    }
+}
+
+// Notes still waiting to be played when the level ends went by unplayed, so they're misses too
+pub fn miss_remaining_notes(
+    mut due_notes: ResMut<DueNotes>,
+    mut score: ResMut<LevelScore>,
+    mut stats: ResMut<LevelStats>,
+    settings: Res<Settings>,
+) {
+    let rules = settings.scoring_rules();
+    for note in due_notes.0.drain(..) {
+        debug!("Missed Chord -> {}, Fret -> {}", note.chord, note.fret);
+        *stats.grades.entry(Grade::Miss).or_default() += 1;
+        score.add(Grade::Miss, &rules);
+    }
+}
+
+pub fn reset_score(
+    mut score: ResMut<LevelScore>,
+    mut stats: ResMut<LevelStats>,
+    mut due_notes: ResMut<DueNotes>,
+) {
+    *score = LevelScore::default();
+    *stats = LevelStats::default();
+    due_notes.0.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Difficulty;
+
+    fn score_streak(length: usize, rules: &ScoringRules) -> LevelScore {
+        let mut score = LevelScore::default();
+        for _ in 0..length {
+            score.add(Grade::Perfect, rules);
+        }
+        score
+    }
+
+    #[test]
+    fn note_reaching_a_tier_scores_with_its_multiplier() {
+        let rules = ScoringRules::for_difficulty(Difficulty::Normal);
+        let mut score = score_streak(9, &rules);
+        assert_eq!(score.multiplier, 1);
+        assert_eq!(score.points, 9 * rules.perfect_points);
+
+        score.add(Grade::Perfect, &rules);
+        assert_eq!(score.streak, 10);
+        assert_eq!(score.multiplier, 2);
+        assert_eq!(score.points, 9 * rules.perfect_points + 2 * rules.perfect_points);
+    }
+
+    #[test]
+    fn miss_ends_the_streak_without_being_multiplied() {
+        let rules = ScoringRules::for_difficulty(Difficulty::Normal);
+        let mut score = score_streak(12, &rules);
+        let points = score.points;
+
+        score.add(Grade::Miss, &rules);
+        assert_eq!(score.points, points + rules.miss_points);
+        assert_eq!(score.streak, 0);
+        assert_eq!(score.multiplier, 1);
+        assert_eq!(score.max_streak, 12);
+    }
+
+    #[test]
+    fn good_ends_the_streak_on_hard() {
+        let rules = ScoringRules::for_difficulty(Difficulty::Hard);
+        let mut score = score_streak(15, &rules);
+        assert_eq!(score.multiplier, 2);
+        let points = score.points;
+
+        score.add(Grade::Good, &rules);
+        assert_eq!(score.streak, 0);
+        assert_eq!(score.multiplier, 1);
+        assert_eq!(score.points, points + rules.good_points);
+        assert_eq!(score.max_streak, 15);
+    }
+
+    #[test]
+    fn good_keeps_the_streak_on_normal() {
+        let rules = ScoringRules::for_difficulty(Difficulty::Normal);
+        let mut score = score_streak(9, &rules);
+
+        score.add(Grade::Good, &rules);
+        assert_eq!(score.streak, 10);
+        assert_eq!(score.points, 9 * rules.perfect_points + 2 * rules.good_points);
+    }
+
+    fn stats(perfect: usize, great: usize, good: usize, miss: usize) -> LevelStats {
+        LevelStats {
+            grades: HashMap::from([
+                (Grade::Perfect, perfect),
+                (Grade::Great, great),
+                (Grade::Good, good),
+                (Grade::Miss, miss),
+            ]),
+        }
+    }
+
+    #[test]
+    fn no_notes_is_zero_accuracy() {
+        let stats = LevelStats::default();
+        assert_eq!(stats.notes(), 0);
+        assert_eq!(stats.accuracy(), 0.);
+        assert_eq!(stats.letter_grade(), "F");
+    }
+
+    #[test]
+    fn great_and_good_count_for_part_of_a_note() {
+        assert_eq!(stats(1, 1, 1, 1).accuracy(), (1. + 0.75 + 0.5) / 4. * 100.);
+        assert_eq!(stats(0, 0, 0, 3).accuracy(), 0.);
+    }
+
+    #[test]
+    fn letter_grade_starts_at_each_boundary() {
+        assert_eq!(stats(19, 0, 0, 1).letter_grade(), "S");
+        assert_eq!(stats(18, 0, 0, 2).letter_grade(), "A");
+        assert_eq!(stats(8, 0, 0, 2).letter_grade(), "B");
+        assert_eq!(stats(7, 0, 0, 3).letter_grade(), "C");
+        assert_eq!(stats(6, 0, 0, 4).letter_grade(), "D");
+        assert_eq!(stats(59, 0, 0, 41).letter_grade(), "F");
+        assert_eq!(stats(0, 0, 0, 1).letter_grade(), "F");
+    }
+}
@@ -0,0 +1,575 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use crate::bass::pitch_detector::{decibels_to_amplitude, DetectorAlgorithm};
+use crate::bass::tuning::Tuning;
+use crate::input::Grade;
+use crate::profile::Profile;
+
+// Which input the pitch detector listens to. None means the system default for the host and
+// device, and mixing every channel down for the channel.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct InputSettings {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub channel: Option<usize>,
+}
+
+// How the pitch detector slides over the input. Every `hop_size` new samples it analyses the
+// last `window_size` samples, so a smaller hop means less latency for more CPU time. The window
+// has to fit a couple of periods of the low B string (about 32 ms each).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DetectorSettings {
+    pub algorithm: DetectorAlgorithm,
+    // check each estimate against the spectrum for octave and harmonic mistakes
+    pub octave_correction: bool,
+    pub window_size: usize,
+    pub hop_size: usize,
+}
+
+impl Default for DetectorSettings {
+    fn default() -> Self {
+        DetectorSettings {
+            algorithm: DetectorAlgorithm::default(),
+            octave_correction: true,
+            window_size: 4096,
+            hop_size: 512,
+        }
+    }
+}
+
+// The filters run over the input before pitch detection
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FilterSettings {
+    pub dc_blocker: bool,
+    // 50 or 60 Hz depending on where you live, None leaves hum alone
+    pub mains_frequency: Option<f64>,
+    // how many multiples of the mains frequency get a notch, counting the mains frequency itself
+    pub mains_harmonics: usize,
+    // cut everything below the lowest note and above the harmonics the detectors use
+    pub band_pass: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            dc_blocker: true,
+            mains_frequency: None,
+            mains_harmonics: 4,
+            band_pass: true,
+        }
+    }
+}
+
+// Input levels for one device, every interface comes in at its own level. Everything is in dB
+// relative to full scale.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct GateSettings {
+    pub gain_db: f64,
+    // windows quieter than this (after the gain) are ignored so string noise can't hit notes
+    pub noise_gate_db: f64,
+    // how periodic a window has to be (0..1) for its pitch to count
+    pub clarity: f64,
+}
+
+impl Default for GateSettings {
+    fn default() -> Self {
+        GateSettings {
+            gain_db: 0.,
+            noise_gate_db: -26.,
+            clarity: 0.6,
+        }
+    }
+}
+
+impl GateSettings {
+    pub fn gain(&self) -> f64 {
+        decibels_to_amplitude(self.gain_db)
+    }
+
+    pub fn noise_gate(&self) -> f64 {
+        decibels_to_amplitude(self.noise_gate_db)
+    }
+}
+
+// How far off (in cents) the detector hears one string of the player's bass, measured by the
+// string calibration screen at a few frets. Intonation drifts along the neck so frets in between
+// are interpolated.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct StringCalibration {
+    // keyed by fret
+    pub offsets: BTreeMap<i8, f64>,
+}
+
+impl StringCalibration {
+    pub fn offset(&self, fret: i8) -> f64 {
+        let below = self.offsets.range(..=fret).next_back();
+        let above = self.offsets.range(fret..).next();
+
+        match (below, above) {
+            (Some((low_fret, low)), Some((high_fret, high))) if low_fret != high_fret => {
+                low + (high - low) * (fret - low_fret) as f64 / (high_fret - low_fret) as f64
+            }
+            (Some((_, offset)), _) | (None, Some((_, offset))) => *offset,
+            (None, None) => 0.,
+        }
+    }
+}
+
+// How far either side of a note (in ms) a pluck can land for each grade. Anything outside the
+// Good window doesn't play the note.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct JudgementSettings {
+    pub perfect_ms: f64,
+    pub great_ms: f64,
+    pub good_ms: f64,
+    // what each grade scored before there were difficulties, only read so Settings::load can move
+    // them over to the Normal scoring rules
+    #[serde(skip_serializing)]
+    perfect_points: Option<i32>,
+    #[serde(skip_serializing)]
+    great_points: Option<i32>,
+    #[serde(skip_serializing)]
+    good_points: Option<i32>,
+    #[serde(skip_serializing)]
+    miss_points: Option<i32>,
+}
+
+impl Default for JudgementSettings {
+    fn default() -> Self {
+        JudgementSettings {
+            perfect_ms: 40.,
+            great_ms: 80.,
+            good_ms: 130.,
+            perfect_points: None,
+            great_points: None,
+            good_points: None,
+            miss_points: None,
+        }
+    }
+}
+
+impl JudgementSettings {
+    // Grade for a pluck `offset` seconds away from its note
+    pub fn grade(&self, offset: f64) -> Grade {
+        let offset_ms = offset.abs() * 1000.;
+        if offset_ms <= self.perfect_ms {
+            Grade::Perfect
+        } else if offset_ms <= self.great_ms {
+            Grade::Great
+        } else if offset_ms <= self.good_ms {
+            Grade::Good
+        } else {
+            Grade::Miss
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    // The one after this, wrapping back around to Easy
+    pub fn next(&self) -> Difficulty {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+}
+
+// What each grade scores on one difficulty and how the streak of notes hit in a row builds the
+// multiplier. Hits are worth their points times the multiplier, a miss costs `miss_points` and
+// ends the streak.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ScoringRules {
+    pub perfect_points: i32,
+    pub great_points: i32,
+    pub good_points: i32,
+    pub miss_points: i32,
+    // the streak each multiplier above 1x starts at, lowest first. [10, 20, 30] is 2x from the
+    // 10th note in a row, 3x from the 20th and 4x from the 30th on.
+    pub multiplier_streaks: Vec<usize>,
+    // whether a Good keeps the streak going, when it doesn't it ends it like a miss without the
+    // penalty
+    pub good_keeps_streak: bool,
+    // how many cents off the note a pluck can be and still play it, lower is stricter
+    pub hit_tolerance_cents: f64,
+}
+
+impl Default for ScoringRules {
+    fn default() -> Self {
+        ScoringRules::for_difficulty(Difficulty::default())
+    }
+}
+
+impl ScoringRules {
+    pub fn for_difficulty(difficulty: Difficulty) -> ScoringRules {
+        match difficulty {
+            Difficulty::Easy => ScoringRules {
+                perfect_points: 25,
+                great_points: 20,
+                good_points: 15,
+                miss_points: 0,
+                multiplier_streaks: vec![8, 16, 24],
+                good_keeps_streak: true,
+                hit_tolerance_cents: 70.,
+            },
+            Difficulty::Normal => ScoringRules {
+                perfect_points: 25,
+                great_points: 15,
+                good_points: 10,
+                miss_points: -10,
+                multiplier_streaks: vec![10, 20, 30],
+                good_keeps_streak: true,
+                hit_tolerance_cents: 50.,
+            },
+            Difficulty::Hard => ScoringRules {
+                perfect_points: 30,
+                great_points: 15,
+                good_points: 5,
+                miss_points: -20,
+                multiplier_streaks: vec![15, 30, 45],
+                good_keeps_streak: false,
+                hit_tolerance_cents: 35.,
+            },
+        }
+    }
+
+    pub fn points(&self, grade: Grade) -> i32 {
+        match grade {
+            Grade::Perfect => self.perfect_points,
+            Grade::Great => self.great_points,
+            Grade::Good => self.good_points,
+            Grade::Miss => self.miss_points,
+        }
+    }
+
+    pub fn keeps_streak(&self, grade: Grade) -> bool {
+        match grade {
+            Grade::Perfect | Grade::Great => true,
+            Grade::Good => self.good_keeps_streak,
+            Grade::Miss => false,
+        }
+    }
+
+    pub fn multiplier(&self, streak: usize) -> i32 {
+        1 + self.multiplier_streaks.iter().filter(|start| streak >= **start).count() as i32
+    }
+}
+
+// The scoring rules of every difficulty, filled in with the defaults so they're all there to
+// change in settings.json
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScoringTable(pub BTreeMap<Difficulty, ScoringRules>);
+
+impl Default for ScoringTable {
+    fn default() -> Self {
+        ScoringTable(Difficulty::ALL.iter()
+            .map(|difficulty| (*difficulty, ScoringRules::for_difficulty(*difficulty)))
+            .collect())
+    }
+}
+
+// Everything the player can change that should survive a restart
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub input: InputSettings,
+    pub detector: DetectorSettings,
+    pub filter: FilterSettings,
+    // keyed by device name
+    pub gates: HashMap<String, GateSettings>,
+    // keyed by tuning name then string name. A string plays a different note in another tuning so
+    // what was measured in one doesn't carry over.
+    pub string_calibrations: HashMap<String, HashMap<String, StringCalibration>>,
+    // calibrations from before they were kept per tuning, keyed by string name. Only read so
+    // Settings::load can move them under the bass's tuning.
+    #[serde(skip_serializing)]
+    strings: HashMap<String, StringCalibration>,
+    // go straight into a level without checking the open strings against the chart's tuning
+    pub skip_tuning_check: bool,
+    // how long after the song plays a note the player's pluck of it is heard by the detector,
+    // the audio output and input buffers together. Measured by the latency test.
+    pub latency_ms: f64,
+    pub judgement: JudgementSettings,
+    pub difficulty: Difficulty,
+    pub scoring: ScoringTable,
+    // preset the player's bass is tuned to, for the tuner and string calibration outside of a
+    // level (levels use their chart's tuning). None is standard.
+    pub tuning: Option<String>,
+}
+
+impl Settings {
+    pub fn gate(&self, device: &str) -> GateSettings {
+        self.gates.get(device).cloned().unwrap_or_default()
+    }
+
+    pub fn gate_mut(&mut self, device: &str) -> &mut GateSettings {
+        self.gates.entry(device.to_string()).or_default()
+    }
+
+    // Cents to take off what the detector hears on a fretted note before judging it
+    pub fn string_offset(&self, tuning: &Tuning, string: &str, fret: i8) -> f64 {
+        self.string_calibrations.get(&tuning.name)
+            .and_then(|strings| strings.get(string))
+            .map_or(0., |calibration| calibration.offset(fret))
+    }
+
+    pub fn string_calibrations_mut(&mut self, tuning: &Tuning) -> &mut HashMap<String, StringCalibration> {
+        self.string_calibrations.entry(tuning.name.clone()).or_default()
+    }
+
+    // Rules for the difficulty being played, the defaults if it's been taken out of the table
+    pub fn scoring_rules(&self) -> ScoringRules {
+        self.scoring.0.get(&self.difficulty).cloned()
+            .unwrap_or_else(|| ScoringRules::for_difficulty(self.difficulty))
+    }
+
+    // Points customised under `judgement` before scoring moved to the difficulty table become the
+    // Normal difficulty's, which is what they were played with
+    fn migrate_judgement_points(&mut self) {
+        let judgement = &mut self.judgement;
+        let points = [
+            (judgement.perfect_points.take(), Grade::Perfect),
+            (judgement.great_points.take(), Grade::Great),
+            (judgement.good_points.take(), Grade::Good),
+            (judgement.miss_points.take(), Grade::Miss),
+        ];
+        if points.iter().all(|(points, _)| points.is_none()) {
+            return;
+        }
+
+        let rules = self.scoring.0.entry(Difficulty::Normal)
+            .or_insert_with(|| ScoringRules::for_difficulty(Difficulty::Normal));
+        for (points, grade) in points {
+            let Some(points) = points else { continue };
+            match grade {
+                Grade::Perfect => rules.perfect_points = points,
+                Grade::Great => rules.great_points = points,
+                Grade::Good => rules.good_points = points,
+                Grade::Miss => rules.miss_points = points,
+            }
+        }
+    }
+
+    // Calibrations from before they were kept per tuning were made with the bass in its own tuning,
+    // the only one the calibration screen went by
+    fn migrate_string_calibrations(&mut self) {
+        if self.strings.is_empty() {
+            return;
+        }
+        let tuning = self.tuning();
+        let strings = std::mem::take(&mut self.strings);
+        let calibrations = self.string_calibrations_mut(&tuning);
+        for (string, calibration) in strings {
+            calibrations.entry(string).or_insert(calibration);
+        }
+    }
+
+    pub fn tuning(&self) -> Tuning {
+        self.tuning.as_deref().and_then(Tuning::preset).unwrap_or_default()
+    }
+
+    pub fn latency_seconds(&self) -> f32 {
+        (self.latency_ms / 1000.).max(0.) as f32
+    }
+
+    fn path(profile: &Profile) -> PathBuf {
+        profile.dir().join("settings.json")
+    }
+
+    // Falls back on the defaults if there's nothing saved yet or the file can't be read
+    pub fn load(profile: &Profile) -> Settings {
+        let path = Settings::path(profile);
+        if !path.exists() {
+            return Settings::default();
+        }
+
+        match Settings::read(&path) {
+            Ok(mut settings) => {
+                settings.migrate_judgement_points();
+                settings.migrate_string_calibrations();
+                settings
+            }
+            Err(err) => {
+                println!("Couldn't read {}: {}", path.display(), err);
+                Settings::default()
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<Settings, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, profile: &Profile) -> Result<(), Box<dyn Error>> {
+        let path = Settings::path(profile);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+
+        Ok(())
+    }
+}
+
+// Per user directory for everything we write to disk, following each platform's convention
+pub fn data_dir() -> PathBuf {
+    let base = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    };
+
+    base.unwrap_or_else(|| PathBuf::from(".")).join("bass_master")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grade_includes_the_edge_of_each_window() {
+        let judgement = JudgementSettings::default();
+        assert_eq!(judgement.grade(0.), Grade::Perfect);
+        assert_eq!(judgement.grade(judgement.perfect_ms / 1000.), Grade::Perfect);
+        assert_eq!(judgement.grade(judgement.great_ms / 1000.), Grade::Great);
+        assert_eq!(judgement.grade(judgement.good_ms / 1000.), Grade::Good);
+    }
+
+    #[test]
+    fn grade_past_each_window_drops_a_grade() {
+        let judgement = JudgementSettings::default();
+        assert_eq!(judgement.grade((judgement.perfect_ms + 1.) / 1000.), Grade::Great);
+        assert_eq!(judgement.grade((judgement.great_ms + 1.) / 1000.), Grade::Good);
+        assert_eq!(judgement.grade((judgement.good_ms + 1.) / 1000.), Grade::Miss);
+    }
+
+    #[test]
+    fn grade_is_the_same_early_or_late() {
+        let judgement = JudgementSettings::default();
+        assert_eq!(judgement.grade(-judgement.perfect_ms / 1000.), Grade::Perfect);
+        assert_eq!(judgement.grade(-(judgement.great_ms + 1.) / 1000.), Grade::Good);
+        assert_eq!(judgement.grade(-(judgement.good_ms + 1.) / 1000.), Grade::Miss);
+    }
+
+    #[test]
+    fn multiplier_goes_up_on_the_streak_each_tier_starts_at() {
+        let rules = ScoringRules::for_difficulty(Difficulty::Normal);
+        assert_eq!(rules.multiplier(0), 1);
+        assert_eq!(rules.multiplier(9), 1);
+        assert_eq!(rules.multiplier(10), 2);
+        assert_eq!(rules.multiplier(19), 2);
+        assert_eq!(rules.multiplier(20), 3);
+        assert_eq!(rules.multiplier(30), 4);
+        assert_eq!(rules.multiplier(100), 4);
+    }
+
+    #[test]
+    fn good_keeps_the_streak_unless_on_hard() {
+        let normal = ScoringRules::for_difficulty(Difficulty::Normal);
+        let hard = ScoringRules::for_difficulty(Difficulty::Hard);
+        assert!(normal.keeps_streak(Grade::Good));
+        assert!(!hard.keeps_streak(Grade::Good));
+        assert!(hard.keeps_streak(Grade::Great));
+        assert!(!normal.keeps_streak(Grade::Miss));
+    }
+
+    #[test]
+    fn harder_difficulties_judge_pitch_more_strictly() {
+        let tolerance = |difficulty| ScoringRules::for_difficulty(difficulty).hit_tolerance_cents;
+        assert!(tolerance(Difficulty::Easy) > tolerance(Difficulty::Normal));
+        assert!(tolerance(Difficulty::Normal) > tolerance(Difficulty::Hard));
+    }
+
+    #[test]
+    fn calibration_offset_interpolates_between_measured_frets() {
+        let calibration = StringCalibration { offsets: BTreeMap::from([(0, 10.), (5, 20.), (12, -15.)]) };
+        assert_eq!(calibration.offset(0), 10.);
+        assert_eq!(calibration.offset(5), 20.);
+        assert_eq!(calibration.offset(3), 16.);
+        assert_eq!(calibration.offset(7), 10.);
+    }
+
+    #[test]
+    fn calibration_offset_holds_past_the_measured_frets() {
+        let calibration = StringCalibration { offsets: BTreeMap::from([(5, 20.), (12, -15.)]) };
+        assert_eq!(calibration.offset(0), 20.);
+        assert_eq!(calibration.offset(24), -15.);
+        assert_eq!(StringCalibration::default().offset(7), 0.);
+    }
+
+    #[test]
+    fn string_offsets_only_apply_in_the_tuning_they_were_measured_in() {
+        let mut settings = Settings::default();
+        let calibration = StringCalibration { offsets: BTreeMap::from([(0, 10.)]) };
+        settings.string_calibrations_mut(&Tuning::standard()).insert("E".to_string(), calibration);
+
+        assert_eq!(settings.string_offset(&Tuning::standard(), "E", 0), 10.);
+        assert_eq!(settings.string_offset(&Tuning::drop_d(), "E", 0), 0.);
+        assert_eq!(settings.string_offset(&Tuning::standard(), "A", 0), 0.);
+    }
+
+    #[test]
+    fn old_string_calibrations_move_under_the_bass_tuning() {
+        let mut settings: Settings = serde_json::from_str(r#"{"tuning": "Drop D", "strings": {"E": {"offsets": {"0": 12.0}}}}"#).unwrap();
+        settings.migrate_string_calibrations();
+
+        assert_eq!(settings.string_offset(&Tuning::drop_d(), "E", 0), 12.);
+        assert_eq!(settings.string_offset(&Tuning::standard(), "E", 0), 0.);
+        let saved = serde_json::to_value(&settings).unwrap();
+        assert!(saved.get("strings").is_none());
+    }
+
+    #[test]
+    fn judgement_points_move_to_normal_scoring() {
+        let mut settings: Settings = serde_json::from_str(r#"{"judgement": {"perfect_points": 50, "miss_points": 0}}"#).unwrap();
+        settings.migrate_judgement_points();
+
+        let normal = &settings.scoring.0[&Difficulty::Normal];
+        assert_eq!(normal.perfect_points, 50);
+        assert_eq!(normal.miss_points, 0);
+        // the rest keep their defaults
+        assert_eq!(normal.great_points, ScoringRules::for_difficulty(Difficulty::Normal).great_points);
+        assert_eq!(settings.scoring.0[&Difficulty::Hard], ScoringRules::for_difficulty(Difficulty::Hard));
+        // and aren't written back under judgement
+        let saved = serde_json::to_value(&settings).unwrap();
+        assert!(saved["judgement"].get("perfect_points").is_none());
+    }
+}
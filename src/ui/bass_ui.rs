@@ -8,7 +8,11 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 
-use crate::{LevelResource, LevelClock, CurrentBassNote};
+use crate::{LevelResource, LevelClock, DueNote, DueNotes};
+use crate::input::{Grade, JudgementEvent};
+use crate::bass::note::{pitch_class_name, MAX_FRET};
+use crate::bass::tuning::{Tuning, TuningJson};
+use crate::settings::Settings;
 
 #[derive(Event)]
 pub struct NoteCollision {
@@ -58,6 +62,9 @@ pub struct MusicJson {
     Duration: f32,
     Beats: i8,
     NoteValue: i8,
+    // charts without a tuning are in standard EADG
+    #[serde(default)]
+    Tuning: Option<TuningJson>,
     Notes: Vec<NoteResource>,
 }
 
@@ -66,11 +73,42 @@ impl MusicJson {
         let json_file = File::open(path)?;
         let reader = BufReader::new(json_file);
         // read JSON contents
-        let tablature = serde_json::from_reader(reader)?;
+        let tablature: MusicJson = serde_json::from_reader(reader)?;
+
+        // every note has to land on a string the tuning actually has and a fret on the neck
+        let tuning = tablature.tuning()?;
+        for note in &tablature.Notes {
+            if tuning.string(&note.String).is_none() {
+                return Err(format!("String {} is not part of the {} tuning", note.String, tuning.name).into());
+            }
+            if !(0..=MAX_FRET).contains(&note.Fret) {
+                return Err(format!("Fret {} is off the neck (0-{})", note.Fret, MAX_FRET).into());
+            }
+        }
 
         Ok(tablature)
     }
 
+    pub fn tuning(&self) -> Result<Tuning, Box<dyn Error>> {
+        match &self.Tuning {
+            Some(tuning) => tuning.to_tuning(),
+            None => Ok(Tuning::standard()),
+        }
+    }
+
+    // When each note should be played, in seconds from the start of the song. Every note lasts
+    // until the next one starts.
+    pub fn note_times(&self) -> Vec<f64> {
+        let beat_seconds = 60. / self.BPM as f64;
+        self.Notes.iter()
+            .scan(0., |time, note| {
+                let start = *time;
+                *time += beat_seconds * self.NoteValue as f64 / note.Note as f64;
+                Some(start)
+            })
+            .collect()
+    }
+
     pub fn speed_manipulation(&self, note: Option<i8>) -> f32 {
         // scale for the beats per minute
         let bottom_value;
@@ -142,56 +180,11 @@ impl Index<i8> for BassNoteAssets {
     } 
 }
 
-#[derive(AssetCollection, Resource)]
-pub struct FretNumberAssets {
-    #[asset(key = "fret.0")]
-    zero: Handle<TextureAtlas>,
-    #[asset(key = "fret.1")]
-    one: Handle<TextureAtlas>,
-    #[asset(key = "fret.2")]
-    two: Handle<TextureAtlas>,
-    #[asset(key = "fret.3")]
-    three: Handle<TextureAtlas>,
-    #[asset(key = "fret.4")]
-    four: Handle<TextureAtlas>,
-    #[asset(key = "fret.5")]
-    five: Handle<TextureAtlas>,
-    #[asset(key = "fret.6")]
-    six: Handle<TextureAtlas>,
-    #[asset(key = "fret.7")]
-    seven: Handle<TextureAtlas>,
-    #[asset(key = "fret.8")]
-    eight: Handle<TextureAtlas>,
-    #[asset(key = "fret.9")]
-    nine: Handle<TextureAtlas>,
-    #[asset(key = "fret.10")]
-    ten: Handle<TextureAtlas>,
-}
-
-impl Index<i8> for FretNumberAssets {
-    type Output = Handle<TextureAtlas>;
-    fn index(&self, n: i8) -> &Handle<TextureAtlas> {
-        match n {
-            0 => &self.zero,
-            1 => &self.one,
-            2 => &self.two,
-            3 => &self.three,
-            4 => &self.four,
-            5 => &self.five,
-            6 => &self.six,
-            7 => &self.seven,
-            8 => &self.eight,
-            9 => &self.nine,
-            10 => &self.ten,
-            _ => panic!("!! Out of scope of our note assets !!")
-        }
-    } 
-}
-
 const HORIZONTAL_BASS_WIDTH: f32 = 450.;
 const VERTICAL_BASS_HEIGHT: f32 = 50.;
 const NOTE_WIDTH: f32 = 10.;
 const NOTE_OFFSET: f32 = 10.;
+const MISS_FLASH_SECONDS: f32 = 0.3;
 
 pub fn spawn_bass_ui(
     mut commands: Commands,
@@ -199,6 +192,7 @@ pub fn spawn_bass_ui(
     _bass_note_assets: Res<BassNoteAssets>,
     vertical_bass_strum_asset: Res<VerticalBassStrumAsset>,
     bass_string_asset: Res<BassStringAsset>,
+    tuning: Res<Tuning>,
 )  {
     // spawn for guitar UI strings and picks
     commands.spawn((SpriteSheetBundle {
@@ -214,13 +208,16 @@ pub fn spawn_bass_ui(
     },
         BassUI,
     )).with_children(|parent| {
-            // spawn for horizontal strings
-            for i in 0..4 {
+            // space the strings evenly down the strum line however many there are
+            let string_spacing = VERTICAL_BASS_HEIGHT / (tuning.strings.len() + 1) as f32;
+
+            // spawn for horizontal strings, highest string on top like tablature
+            for (i, string) in tuning.strings.iter().rev().enumerate() {
                 parent.spawn((
                     SpriteSheetBundle {
                         texture_atlas: bass_string_asset.sprite.clone(),
                         // translate the y-axis down for each string
-                        transform: Transform::from_xyz(0.0, (-10. * i as f32) - 10., 50.0),
+                        transform: Transform::from_xyz(0.0, (-string_spacing * i as f32) - string_spacing, 50.0),
                         sprite: TextureAtlasSprite {
                             custom_size: Some(Vec2::new(450., 1.5)),
                             anchor: Anchor::CenterLeft,
@@ -230,14 +227,24 @@ pub fn spawn_bass_ui(
                         ..default()
                     },
                     // Component for querying specific strings
-                    match i {
-                        0 => BassString("G".to_string()),
-                        1 => BassString("D".to_string()),
-                        2 => BassString("A".to_string()),
-                        3 => BassString("E".to_string()),
-                        _ => panic!("No string correlates to i-index")
-                    },
-                ));
+                    BassString(string.name.clone()),
+                )).with_children(|parent| {
+                        // label the string with the note it's tuned to
+                        parent.spawn(Text2dBundle {
+                            text: Text::from_section(
+                                pitch_class_name(string.midi),
+                                TextStyle {
+                                    font_size: 32.0,
+                                    color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                                    ..default()
+                                }
+                            ),
+                            text_anchor: Anchor::CenterRight,
+                            // render the font large and scale it down so it stays sharp
+                            transform: Transform::from_xyz(-2.0, 0.0, 0.0).with_scale(Vec3::splat(0.2)),
+                            ..default()
+                        });
+                    });
             }
             // spawn for bass pick
             parent.spawn((SpriteSheetBundle {
@@ -266,13 +273,14 @@ pub fn insert_level_metadata(
     level_path.set_extension("json");
     let level_path = format!("./assets/{}", level_path.to_str().unwrap());
 
-    commands.insert_resource(MusicJson::parse_new(level_path).unwrap());
+    let tablature = MusicJson::parse_new(level_path).unwrap();
+    commands.insert_resource(tablature.tuning().unwrap());
+    commands.insert_resource(tablature);
 }
 
 pub fn spawn_bass_notes(
     mut commands: Commands,
     string_query: Query<(&Parent, &Transform, &BassString), With<Handle<TextureAtlas>>>,
-    fret_number_assets: Res<FretNumberAssets>,
     bass_note_assets: Res<BassNoteAssets>,
     tablature: Res<MusicJson>,
 ) {
@@ -304,15 +312,19 @@ pub fn spawn_bass_notes(
                         note: note.Note,
                     }
                 )).with_children(|parent| {
+                        // fret numbers are drawn as text so any fret on the neck can be shown
                         parent.spawn((
-                            SpriteSheetBundle {
-                                texture_atlas: fret_number_assets[note.Fret].clone(),
-                                transform: Transform::from_xyz(0., string_y, 500.),
-                                sprite: TextureAtlasSprite {
-                                    anchor: Anchor::Center,
-                                    custom_size: Some(Vec2::splat(5.)),
-                                    ..default()
-                                },
+                            Text2dBundle {
+                                text: Text::from_section(
+                                    note.Fret.to_string(),
+                                    TextStyle {
+                                        font_size: 32.0,
+                                        color: Color::WHITE,
+                                        ..default()
+                                    }
+                                ),
+                                text_anchor: Anchor::Center,
+                                transform: Transform::from_xyz(0., string_y, 500.).with_scale(Vec3::splat(0.15)),
                                 ..default()
                             },
                             BassFrets
@@ -333,29 +345,65 @@ pub fn despawn_bass_ui(
     }
 }
 
+// Every note of the chart is due on its beat, pushed back by the measured latency as the player's
+// pluck is heard that much after the song plays it
+pub fn schedule_due_notes(
+    mut due_notes: ResMut<DueNotes>,
+    tablature: Res<MusicJson>,
+    settings: Res<Settings>,
+) {
+    let latency = settings.latency_seconds() as f64;
+    due_notes.0 = tablature.Notes.iter().zip(tablature.note_times())
+        .map(|(note, time)| DueNote {
+            chord: note.String.clone(),
+            fret: note.Fret,
+            due: time + latency,
+        })
+        .collect();
+}
+
 pub fn write_note_collision(
     mut commands: Commands,
     mut writer: EventWriter<NoteCollision>,
-    mut current_note: ResMut<CurrentBassNote>,
     bass_note_query: Query<(&Transform, &NoteComponent, Entity)>,
     pick_query: Query<&Transform, (With<BassPick>, Without<NoteComponent>)>,
+    tablature: Res<MusicJson>,
+    settings: Res<Settings>,
 ) {
     let pick_transform = pick_query.single();
 
     for (bass_note_transform, bass_note, entity) in &bass_note_query {
-        if bass_note_transform.translation.x <= pick_transform.translation.x &&
-        bass_note_transform.translation.x >= pick_transform.translation.x - 5. {
+        // the player's pluck is heard a little after the note reaches the pick, so the note only
+        // reaches it once it's travelled that much further, in step with when it's judged
+        let speed = (NOTE_WIDTH + NOTE_OFFSET) / tablature.speed_manipulation(Some(bass_note.note));
+        let pick_x = pick_transform.translation.x - speed * settings.latency_seconds();
+        if bass_note_transform.translation.x <= pick_x &&
+        bass_note_transform.translation.x >= pick_x - 5. {
             writer.send(NoteCollision { chord: bass_note.chord.clone(), fret: bass_note.fret });
-            *current_note = CurrentBassNote {
-                chord: bass_note.chord.clone(),
-                fret: bass_note.fret,
-            };
             commands.entity(entity).despawn_recursive();
         }
     }
 }
 
 
+// Flash the pick red when a note goes by unplayed, fading back over MISS_FLASH_SECONDS
+pub fn flash_pick_on_miss(
+    mut judgement_events: EventReader<JudgementEvent>,
+    mut pick_query: Query<&mut TextureAtlasSprite, With<BassPick>>,
+    mut remaining: Local<f32>,
+    time: Res<Time>,
+) {
+    if judgement_events.iter().any(|judgement| judgement.grade == Grade::Miss) {
+        *remaining = MISS_FLASH_SECONDS;
+    }
+    *remaining = (*remaining - time.delta_seconds()).max(0.);
+
+    let red = *remaining / MISS_FLASH_SECONDS;
+    for mut sprite in pick_query.iter_mut() {
+        sprite.color = Color::rgb(1., 1. - red, 1. - red);
+    }
+}
+
 pub fn translate_bass_notes(
     mut bass_note_query: Query<(&mut Transform, &mut Visibility, &NoteComponent), (With<NoteComponent>, Without<BassPick>)>,
     mut intro_timer: ResMut<IntroTimer>,
@@ -0,0 +1,274 @@
+use bevy::prelude::*;
+use belly::prelude::*;
+use std::collections::HashMap;
+use crate::GameState;
+use crate::bass::note::{cents_from_midi, note_name, Pitch};
+use crate::bass::pitch_detector::{PitchEvent, PluckEvent};
+use crate::bass::tuning::Tuning;
+use crate::profile::Profile;
+use crate::settings::{Settings, StringCalibration};
+use crate::ui::settings::BackEvent;
+
+// frets measured on every string, open, the middle of the neck and the octave
+const CALIBRATION_FRETS: [i8; 3] = [0, 5, 12];
+// estimates averaged for each note, about a fifth of a second of it ringing
+const READINGS_PER_NOTE: usize = 16;
+// anything further off than this is a different note, not a badly intonated one
+const MAX_OFFSET_CENTS: f64 = 100.;
+
+// StringCalibrationEvent opens the string calibration screen from the settings screen
+#[derive(Event)]
+pub struct StringCalibrationEvent;
+
+// SkipNoteEvent moves on without measuring the current note, RestartCalibrationEvent starts over
+#[derive(Event)]
+pub struct SkipNoteEvent;
+
+#[derive(Event)]
+pub struct RestartCalibrationEvent;
+
+#[derive(Component)]
+pub struct CalibrationStatusUI;
+
+struct CalibrationStep {
+    string: String,
+    fret: i8,
+    midi: i32,
+}
+
+// Walks the player through plucking every string at each of CALIBRATION_FRETS and keeps how far
+// off the detector hears each one. Nothing is saved until every step is done.
+#[derive(Resource)]
+pub struct StringCalibrationWizard {
+    steps: Vec<CalibrationStep>,
+    current: usize,
+    // a pluck of the right note was heard and its estimates are being collected
+    listening: bool,
+    readings: Vec<f64>,
+    // the last note plucked that wasn't the one asked for
+    wrong_note: Option<Pitch>,
+    measured: HashMap<String, StringCalibration>,
+}
+
+impl StringCalibrationWizard {
+    fn new(tuning: &Tuning) -> StringCalibrationWizard {
+        StringCalibrationWizard {
+            steps: tuning.strings.iter()
+                .flat_map(|string| CALIBRATION_FRETS.iter().map(|fret| CalibrationStep {
+                    string: string.name.clone(),
+                    fret: *fret,
+                    midi: string.midi + *fret as i32,
+                }))
+                .collect(),
+            current: 0,
+            listening: false,
+            readings: Vec::new(),
+            wrong_note: None,
+            measured: HashMap::new(),
+        }
+    }
+
+    fn next_step(&mut self) {
+        self.current += 1;
+        self.listening = false;
+        self.readings.clear();
+        self.wrong_note = None;
+    }
+
+    fn finished(&self) -> bool {
+        self.current >= self.steps.len()
+    }
+}
+
+pub fn string_calibration_event(
+    mut string_calibration_event: EventReader<StringCalibrationEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _calibration in string_calibration_event.iter() {
+        change_game_state.set(GameState::StringCalibration);
+    }
+}
+
+// Back goes to the settings screen the calibration (or latency test) was opened from
+pub fn calibration_back_event(
+    mut back_event: EventReader<BackEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _back in back_event.iter() {
+        change_game_state.set(GameState::Settings);
+    }
+}
+
+pub fn start_string_calibration(
+    mut commands: Commands,
+    tuning: Res<Tuning>,
+) {
+    commands.insert_resource(StringCalibrationWizard::new(&tuning));
+}
+
+pub fn end_string_calibration(
+    mut commands: Commands,
+) {
+    commands.remove_resource::<StringCalibrationWizard>();
+}
+
+pub fn update_string_calibration(
+    mut pluck_events: EventReader<PluckEvent>,
+    mut pitch_events: EventReader<PitchEvent>,
+    mut skip_event: EventReader<SkipNoteEvent>,
+    mut restart_event: EventReader<RestartCalibrationEvent>,
+    mut wizard: ResMut<StringCalibrationWizard>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+    tuning: Res<Tuning>,
+) {
+    if restart_event.iter().count() > 0 {
+        *wizard = StringCalibrationWizard::new(&tuning);
+        pluck_events.clear();
+        pitch_events.clear();
+        return;
+    }
+    for _skip in skip_event.iter() {
+        if !wizard.finished() {
+            wizard.next_step();
+        }
+    }
+    if wizard.finished() {
+        pluck_events.clear();
+        pitch_events.clear();
+        return;
+    }
+
+    let wizard = &mut *wizard;
+    let step = &wizard.steps[wizard.current];
+
+    // only start listening on a pluck of the right note so string noise and the last note ringing
+    // on don't count
+    for pluck in pluck_events.iter() {
+        let cents = cents_from_midi(pluck.frequency, step.midi);
+        if cents.abs() <= MAX_OFFSET_CENTS {
+            wizard.listening = true;
+            wizard.wrong_note = None;
+        } else {
+            wizard.wrong_note = Pitch::from_frequency(pluck.frequency);
+        }
+    }
+    for pitch in pitch_events.iter() {
+        let cents = cents_from_midi(pitch.frequency, step.midi);
+        if wizard.listening && cents.abs() <= MAX_OFFSET_CENTS {
+            wizard.readings.push(cents);
+        }
+    }
+    if wizard.readings.len() < READINGS_PER_NOTE {
+        return;
+    }
+
+    // the median shrugs off the odd estimate taken during the attack
+    wizard.readings.sort_by(|a, b| a.total_cmp(b));
+    let offset = wizard.readings[wizard.readings.len() / 2];
+    wizard.measured.entry(step.string.clone()).or_default().offsets.insert(step.fret, offset);
+    wizard.next_step();
+
+    if wizard.finished() {
+        // strings that were skipped entirely keep what they had
+        for (string, calibration) in wizard.measured.iter() {
+            settings.string_calibrations_mut(&tuning).insert(string.clone(), calibration.clone());
+        }
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+// Using Belly, draw the buttons of the calibration screen. What to play is shown separately.
+pub fn draw_string_calibration_ui(
+    mut commands: Commands,
+) {
+    let back = commands.spawn_empty().id();
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:settings-wrapper>
+                <button on:press=|ctx| ctx.send_event(BackEvent) c:control><img {back} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+                <button c:setting on:press=|ctx| ctx.send_event(SkipNoteEvent)><strong>"Skip Note"</strong></button>
+                <button c:setting on:press=|ctx| ctx.send_event(RestartCalibrationEvent)><strong>"Start Over"</strong></button>
+            </div>
+        </body>
+    });
+}
+
+pub fn spawn_calibration_status(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                bottom: Val::Percent(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        CalibrationStatusUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            CalibrationStatusUI));
+    });
+}
+
+pub fn update_calibration_status(
+    mut status_text: Query<&mut Text, With<CalibrationStatusUI>>,
+    wizard: Res<StringCalibrationWizard>,
+    settings: Res<Settings>,
+    tuning: Res<Tuning>,
+) {
+    let status = match wizard.steps.get(wizard.current) {
+        Some(step) => {
+            let note = match step.fret {
+                0 => format!("the open {} string ({})", step.string, note_name(step.midi)),
+                fret => format!("fret {} on the {} string ({})", fret, step.string, note_name(step.midi)),
+            };
+            let progress = format!("{}/{}", wizard.current + 1, wizard.steps.len());
+            match (&wizard.wrong_note, wizard.listening) {
+                (Some(heard), _) => format!("Heard {}, pluck {} - {}", heard.name(), note, progress),
+                (None, true) => format!("Let {} ring... - {}", note, progress),
+                (None, false) => format!("Pluck {} - {}", note, progress),
+            }
+        }
+        None => {
+            let offsets: Vec<String> = wizard.steps.iter()
+                .filter(|step| step.fret == 0)
+                .map(|step| format!("{} {:+.0}c", step.string, settings.string_offset(&tuning, &step.string, 0)))
+                .collect();
+            format!("Calibrated: {}", offsets.join(", "))
+        }
+    };
+
+    for mut text in status_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = status.clone();
+        }
+    }
+}
+
+pub fn despawn_calibration_status(
+    mut commands: Commands,
+    status_query: Query<Entity, (With<CalibrationStatusUI>, Without<Text>)>,
+) {
+    for status in status_query.iter() {
+        commands.entity(status).despawn_recursive();
+    }
+}
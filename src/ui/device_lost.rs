@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+use crate::GameState;
+use crate::bass::device::input_device_available;
+use crate::bass::pitch_detector::{AudioStream, DeviceLostEvent, RestartAudioEvent};
+use crate::settings::Settings;
+
+// how often to look for the device again once it's gone
+const RECONNECT_SECONDS: f32 = 2.;
+
+// Present while the input device is gone, until a stream is running again
+#[derive(Resource)]
+pub struct DeviceLost {
+    device: String,
+    reconnect_timer: Timer,
+}
+
+#[derive(Component)]
+pub struct DeviceLostUI;
+
+// Drops the dead stream and pauses the game so no notes go by unheard
+pub fn device_lost_event(
+    mut commands: Commands,
+    mut device_lost_events: EventReader<DeviceLostEvent>,
+    audio_stream: Option<Res<AudioStream>>,
+    game_state: Res<State<GameState>>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    let Some(lost) = device_lost_events.iter().last() else { return };
+    let device = audio_stream.map(|stream| stream.device.clone()).unwrap_or_default();
+    println!("Lost the input device {}: {}", device, lost.0);
+
+    commands.remove_resource::<AudioStream>();
+    commands.insert_resource(DeviceLost {
+        device,
+        reconnect_timer: Timer::from_seconds(RECONNECT_SECONDS, TimerMode::Repeating),
+    });
+    if game_state.get() == &GameState::InGame {
+        change_game_state.set(GameState::Paused);
+    }
+}
+
+// Reopens the stream once the device is plugged back in
+pub fn reconnect_audiostream(
+    mut commands: Commands,
+    time: Res<Time>,
+    device_lost: Option<ResMut<DeviceLost>>,
+    audio_stream: Option<Res<AudioStream>>,
+    settings: Res<Settings>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+) {
+    let Some(mut device_lost) = device_lost else { return };
+    if audio_stream.is_some() {
+        println!("Reconnected to {}", device_lost.device);
+        commands.remove_resource::<DeviceLost>();
+        return;
+    }
+
+    // the game clock is paused along with the game, so tick on real time
+    device_lost.reconnect_timer.tick(time.raw_delta());
+    if device_lost.reconnect_timer.just_finished() && input_device_available(&settings.input) {
+        restart_audio.send(RestartAudioEvent);
+    }
+}
+
+pub fn update_device_lost_message(
+    mut commands: Commands,
+    device_lost: Option<Res<DeviceLost>>,
+    message: Query<Entity, With<DeviceLostUI>>,
+) {
+    match (device_lost, message.is_empty()) {
+        (Some(device_lost), true) => {
+            commands.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        position_type: PositionType::Absolute,
+                        justify_content: JustifyContent::Center,
+                        bottom: Val::Percent(10.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                DeviceLostUI,
+            )).with_children(|parent| {
+                parent.spawn(TextBundle {
+                    text: Text::from_section(
+                        format!("Lost the connection to {}, plug it back in to carry on", device_lost.device),
+                        TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgb(1., 0.3, 0.3),
+                            ..default()
+                        }
+                    ),
+                    ..default()
+                });
+            });
+        }
+        (None, false) => {
+            for entity in message.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+        _ => (),
+    }
+}
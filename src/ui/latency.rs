@@ -0,0 +1,242 @@
+use bevy::prelude::*;
+use bevy::utils::Instant;
+use belly::prelude::*;
+use std::error::Error;
+use std::io::Cursor;
+use crate::GameState;
+use crate::bass::pitch_detector::PluckEvent;
+use crate::bass::synth::click;
+use crate::profile::Profile;
+use crate::settings::Settings;
+use crate::ui::settings::BackEvent;
+
+const CLICK_SAMPLE_RATE: usize = 48000;
+// 80 BPM, slow enough that every pluck is clearly meant for one click
+const CLICK_INTERVAL_SECONDS: f32 = 0.75;
+// clicks to listen to before plucks count, to get into the rhythm
+const COUNT_IN_CLICKS: usize = 4;
+const MEASURED_PLUCKS: usize = 16;
+
+// LatencyTestEvent opens the latency test from the settings screen, RestartLatencyTestEvent
+// throws away what's been measured and starts counting in again
+#[derive(Event)]
+pub struct LatencyTestEvent;
+
+#[derive(Event)]
+pub struct RestartLatencyTestEvent;
+
+#[derive(Component)]
+pub struct LatencyTestUI;
+
+// Plays a click on every beat and measures how long after each one the player's pluck along
+// with it reaches the detector. That covers the audio output, the input buffer and the detector
+// in one go.
+#[derive(Resource)]
+pub struct LatencyTest {
+    click: Handle<AudioSource>,
+    timer: Timer,
+    clicks: Vec<Instant>,
+    // seconds from the nearest click to each pluck, negative when early
+    offsets: Vec<f64>,
+    result: Option<f64>,
+}
+
+impl LatencyTest {
+    fn new(click: Handle<AudioSource>) -> LatencyTest {
+        LatencyTest {
+            click,
+            timer: Timer::from_seconds(CLICK_INTERVAL_SECONDS, TimerMode::Repeating),
+            clicks: Vec::new(),
+            offsets: Vec::new(),
+            result: None,
+        }
+    }
+}
+
+// The click as an in memory WAV so the test doesn't need an asset of its own
+fn click_wav() -> Result<Vec<u8>, Box<dyn Error>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: CLICK_SAMPLE_RATE as u32,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for sample in click(CLICK_SAMPLE_RATE) {
+        writer.write_sample((sample * i16::MAX as f64) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(bytes.into_inner())
+}
+
+pub fn latency_test_event(
+    mut latency_test_event: EventReader<LatencyTestEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _latency_test in latency_test_event.iter() {
+        change_game_state.set(GameState::LatencyTest);
+    }
+}
+
+pub fn start_latency_test(
+    mut commands: Commands,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    match click_wav() {
+        Ok(bytes) => {
+            let click = audio_sources.add(AudioSource { bytes: bytes.into() });
+            commands.insert_resource(LatencyTest::new(click));
+        }
+        Err(err) => {
+            println!("Couldn't make the click for the latency test: {}", err);
+            change_game_state.set(GameState::Settings);
+        }
+    }
+}
+
+pub fn end_latency_test(
+    mut commands: Commands,
+) {
+    commands.remove_resource::<LatencyTest>();
+}
+
+pub fn update_latency_test(
+    mut commands: Commands,
+    mut pluck_events: EventReader<PluckEvent>,
+    mut restart_event: EventReader<RestartLatencyTestEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+    test: Option<ResMut<LatencyTest>>,
+    time: Res<Time>,
+) {
+    let Some(mut test) = test else { return };
+    if restart_event.iter().count() > 0 {
+        let click = test.click.clone();
+        *test = LatencyTest::new(click);
+    }
+    if test.result.is_some() {
+        pluck_events.clear();
+        return;
+    }
+
+    test.timer.tick(time.raw_delta());
+    if test.timer.just_finished() {
+        commands.spawn(AudioBundle {
+            source: test.click.clone(),
+            settings: PlaybackSettings::DESPAWN,
+        });
+        test.clicks.push(Instant::now());
+    }
+
+    let half_interval = CLICK_INTERVAL_SECONDS as f64 / 2.;
+    for pluck in pluck_events.iter() {
+        if test.clicks.len() <= COUNT_IN_CLICKS {
+            continue;
+        }
+        // the attack can be heard before the next click has even been played when the player is
+        // early, so compare against the last click and the one due after it
+        let last = test.clicks[test.clicks.len() - 1];
+        let after_last = pluck.time.saturating_duration_since(last).as_secs_f64();
+        let before_last = last.saturating_duration_since(pluck.time).as_secs_f64();
+        let offset = if after_last > half_interval {
+            after_last - CLICK_INTERVAL_SECONDS as f64
+        } else {
+            after_last - before_last
+        };
+        if offset.abs() < half_interval {
+            test.offsets.push(offset);
+        }
+    }
+
+    if test.offsets.len() >= MEASURED_PLUCKS {
+        // the median so one fluffed pluck doesn't throw it off
+        let mut offsets = test.offsets.clone();
+        offsets.sort_by(|a, b| a.total_cmp(b));
+        let latency = offsets[offsets.len() / 2];
+        test.result = Some(latency);
+
+        settings.latency_ms = (latency * 1000.).round().max(0.);
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+// Using Belly, draw the buttons of the latency test. Progress is shown separately.
+pub fn draw_latency_test_ui(
+    mut commands: Commands,
+) {
+    let back = commands.spawn_empty().id();
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:settings-wrapper>
+                <button on:press=|ctx| ctx.send_event(BackEvent) c:control><img {back} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+                <button c:setting on:press=|ctx| ctx.send_event(RestartLatencyTestEvent)><strong>"Start Over"</strong></button>
+            </div>
+        </body>
+    });
+}
+
+pub fn spawn_latency_test_status(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                bottom: Val::Percent(10.0),
+                ..default()
+            },
+            ..default()
+        },
+        LatencyTestUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            LatencyTestUI));
+    });
+}
+
+pub fn update_latency_test_status(
+    mut status_text: Query<&mut Text, With<LatencyTestUI>>,
+    test: Option<Res<LatencyTest>>,
+    settings: Res<Settings>,
+) {
+    let Some(test) = test else { return };
+    let status = match test.result {
+        Some(_) => format!("Latency {} ms, notes will be judged that much later", settings.latency_ms),
+        None if test.clicks.len() <= COUNT_IN_CLICKS => format!("Get ready to pluck along with the clicks... {}", COUNT_IN_CLICKS + 1 - test.clicks.len()),
+        None => format!("Pluck an open string on every click - {}/{}", test.offsets.len(), MEASURED_PLUCKS),
+    };
+
+    for mut text in status_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = status.clone();
+        }
+    }
+}
+
+pub fn despawn_latency_test_status(
+    mut commands: Commands,
+    status_query: Query<Entity, (With<LatencyTestUI>, Without<Text>)>,
+) {
+    for status in status_query.iter() {
+        commands.entity(status).despawn_recursive();
+    }
+}
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use crate::GameState;
+use crate::settings::Settings;
 
 #[derive(Component)]
 pub struct LoadScreenNodeBundle;
@@ -48,6 +49,7 @@ pub fn exit_load_screen(
     ldtk_levels: Res<Assets<LdtkLevel>>,
     level_query: Query<&Handle<LdtkLevel>>,
     mut level_event: EventReader<LevelEvent>,
+    settings: Res<Settings>,
 ) {
     for level_handle in level_query.iter() {
         // get level iid
@@ -59,7 +61,12 @@ pub fn exit_load_screen(
                 load_screen_query.for_each(|load_screen_node_bundles| {
                     commands.entity(load_screen_node_bundles).despawn_recursive();
                 });
-                change_game_state.set(GameState::InGame);
+                // check the bass is in the chart's tuning first unless the player turned that off
+                if settings.skip_tuning_check {
+                    change_game_state.set(GameState::InGame);
+                } else {
+                    change_game_state.set(GameState::TuningCheck);
+                }
             }
         }
     }
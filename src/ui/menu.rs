@@ -5,6 +5,11 @@ use std::fs;
 use std::error::Error;
 use std::path::Path;
 use crate::GameState;
+use crate::profile::{Profile, Profiles};
+use crate::save_data::{LevelQuit, SaveData};
+use crate::ui::profiles::{NewProfileEvent, ProfileSelectEvent};
+use crate::ui::settings::SettingsEvent;
+use crate::ui::tuner::TunerEvent;
 
 // QuitEvent used in later function, close_event.
 #[derive(Event)]
@@ -41,12 +46,6 @@ fn find_world_files() -> Result<Vec<String>, Box<dyn Error>> {
     Ok(levels)
 }
 
-pub fn load_main_menu(
-    mut change_game_state: ResMut<NextState<GameState>>,
-) {
-    change_game_state.set(GameState::MenuAssetLoading);
-}
-
 // Toggle between presenting options to levels.
 fn toggle_play_menu(
     ctx: &mut EventContext<impl Event>,
@@ -55,6 +54,14 @@ fn toggle_play_menu(
     ctx.select(".play-wrapper").toggle_class("hidden");
 }
 
+// Toggle between presenting options to profiles.
+fn toggle_profile_menu(
+    ctx: &mut EventContext<impl Event>,
+) {
+    ctx.select(".main-wrapper").toggle_class("hidden");
+    ctx.select(".profile-wrapper").toggle_class("hidden");
+}
+
 // If an event from UI is sent to quit the game, then send the event for bevy to read and quit the
 // game.
 pub fn close_event(
@@ -68,10 +75,12 @@ pub fn close_event(
 
 // If an event from UI is sent to exit the level, then change the game state
 pub fn exit_level_event(
+    mut commands: Commands,
     mut exit_event: EventReader<ExitLevelEvent>,
     mut change_game_state: ResMut<NextState<GameState>>, 
 ) {
     for _exit in exit_event.iter() {
+        commands.insert_resource(LevelQuit);
         change_game_state.set(GameState::Ending);
     }
 }
@@ -94,7 +103,10 @@ pub fn despawn_ui(
 
 // Using Belly, create a main menu ui.
 pub fn draw_main_menu_ui(
-    mut commands: Commands
+    mut commands: Commands,
+    save_data: Res<SaveData>,
+    profiles: Res<Profiles>,
+    profile: Res<Profile>,
 ) {
     // image spawns
     let logo = commands.spawn_empty().id();
@@ -102,9 +114,20 @@ pub fn draw_main_menu_ui(
     let settings = commands.spawn_empty().id();
     let quit = commands.spawn_empty().id();
     let exit = commands.spawn_empty().id();
+    let profile_exit = commands.spawn_empty().id();
+
+    let profile_label = format!("Profile: {}", profile.name);
+    let profile_names = profiles.names.clone();
 
-    // Fetch worlds
+    // Fetch worlds, along with the best result of each that's been played
     let worlds = find_world_files().unwrap();
+    let labels: Vec<(String, String)> = worlds.into_iter().map(|world| {
+        let label = match save_data.song(Path::new(&world)) {
+            Some(song) => format!("{} - Best {} ({:.1}%)", world, song.best_score, song.best_accuracy),
+            None => world.clone(),
+        };
+        (world, label)
+    }).collect();
 
     commands.add(StyleSheet::load("stylesheets/Bass.ess"));
     commands.add(eml! {
@@ -114,18 +137,27 @@ pub fn draw_main_menu_ui(
                 <button on:press=toggle_play_menu c:control>
                     <img {play} c:image src="textures/Play-Logo.png" mode="fit"/>
                 </button>
-                <button c:control>
+                <button c:control on:press=|ctx| ctx.send_event(SettingsEvent)>
                     <img {settings} c:image src="textures/Settings-Logo.png" mode="fit"/>
                 </button>
+                <button c:control on:press=|ctx| ctx.send_event(TunerEvent)><strong>"Tuner"</strong></button>
+                <button c:control on:press=toggle_profile_menu><strong>{profile_label}</strong></button>
                 <button c:control on:press=|ctx| ctx.send_event(QuitEvent)>
                     <img {quit} c:image src="textures/Quit-Logo.png" mode="fit"/>
                 </button>
             </div>
             <div class="play-wrapper hidden">
                 <button on:press=toggle_play_menu c:control><img {exit} src="textures/Back-Logo.png" mode="fit" c:image/></button>
-                <for world in=worlds>
-                    <button c:control on:press=move |ctx| ctx.send_event(WorldEvent(world.clone()))><strong>{world.clone()}</strong></button>
+                <for world in=labels>
+                    <button c:control on:press=move |ctx| ctx.send_event(WorldEvent(world.0.clone()))><strong>{world.1.clone()}</strong></button>
+                </for>
+            </div>
+            <div class="profile-wrapper hidden">
+                <button on:press=toggle_profile_menu c:control><img {profile_exit} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+                <for name in=profile_names>
+                    <button c:setting on:press=move |ctx| ctx.send_event(ProfileSelectEvent(name.clone()))><strong>{name.clone()}</strong></button>
                 </for>
+                <button c:setting on:press=|ctx| ctx.send_event(NewProfileEvent)><strong>"New Profile"</strong></button>
             </div>
         </body>
     });
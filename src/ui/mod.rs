@@ -2,3 +2,11 @@ pub mod bass_ui;
 pub mod score;
 pub mod menu;
 pub mod load_screen;
+pub mod settings;
+pub mod device_lost;
+pub mod calibration;
+pub mod tuner;
+pub mod tuning_check;
+pub mod latency;
+pub mod results;
+pub mod profiles;
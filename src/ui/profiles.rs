@@ -0,0 +1,225 @@
+use bevy::prelude::*;
+use belly::prelude::*;
+use crate::GameState;
+use crate::bass::pitch_detector::RestartAudioEvent;
+use crate::profile::{Profile, Profiles};
+use crate::save_data::SaveData;
+use crate::settings::Settings;
+use crate::ui::settings::BackEvent;
+
+// ProfileSelectEvent carries the name of the profile the player picked from the main menu,
+// NewProfileEvent opens the screen to name a new one and CreateProfileEvent makes it
+#[derive(Event)]
+pub struct ProfileSelectEvent(pub String);
+
+#[derive(Event)]
+pub struct NewProfileEvent;
+
+#[derive(Event)]
+pub struct CreateProfileEvent;
+
+#[derive(Component)]
+pub struct NewProfileUI;
+
+// The name being typed on the new profile screen, and why the last one couldn't be used
+#[derive(Resource, Default)]
+pub struct NewProfileName {
+    name: String,
+    error: Option<String>,
+}
+
+// Load everything of `profile` in place of the profile in use. The main menu is drawn again so the
+// level select shows their results.
+//
+// The resources are swapped in place rather than through Commands so the audio stream restart
+// this sends already sees the new profile's settings.
+fn use_profile(
+    profile: Profile,
+    (current, settings, save_data): (&mut Profile, &mut Settings, &mut SaveData),
+    profiles: &mut Profiles,
+    restart_audio: &mut EventWriter<RestartAudioEvent>,
+    change_game_state: &mut NextState<GameState>,
+) {
+    profiles.active = profile.name.clone();
+    if let Err(err) = profiles.save() {
+        println!("Couldn't save profiles: {}", err);
+    }
+
+    *settings = Settings::load(&profile);
+    *save_data = SaveData::load(&profile);
+    *current = profile;
+    // their input device and detector settings
+    restart_audio.send(RestartAudioEvent);
+    change_game_state.set(GameState::MenuAssetLoading);
+}
+
+pub fn profile_select_event(
+    mut profile_select_event: EventReader<ProfileSelectEvent>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+    mut profiles: ResMut<Profiles>,
+    mut profile: ResMut<Profile>,
+    mut settings: ResMut<Settings>,
+    mut save_data: ResMut<SaveData>,
+) {
+    for select in profile_select_event.iter() {
+        if select.0 != profile.name && profiles.names.contains(&select.0) {
+            let selected = Profile { name: select.0.clone() };
+            let active = (&mut *profile, &mut *settings, &mut *save_data);
+            use_profile(selected, active, &mut profiles, &mut restart_audio, &mut change_game_state);
+        }
+    }
+}
+
+pub fn new_profile_event(
+    mut new_profile_event: EventReader<NewProfileEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _new_profile in new_profile_event.iter() {
+        change_game_state.set(GameState::NewProfile);
+    }
+}
+
+// Outside of a level the tuner and string calibration go by the tuning of the player's own bass
+pub fn apply_profile_tuning(
+    mut commands: Commands,
+    settings: Res<Settings>,
+) {
+    commands.insert_resource(settings.tuning());
+}
+
+pub fn start_new_profile(
+    mut commands: Commands,
+) {
+    commands.init_resource::<NewProfileName>();
+}
+
+pub fn end_new_profile(
+    mut commands: Commands,
+) {
+    commands.remove_resource::<NewProfileName>();
+}
+
+// Type the name, Backspace takes the last letter off and Enter makes the profile
+pub fn type_profile_name(
+    mut characters: EventReader<ReceivedCharacter>,
+    mut create_profile: EventWriter<CreateProfileEvent>,
+    mut new_name: ResMut<NewProfileName>,
+    input: Res<Input<KeyCode>>,
+) {
+    for character in characters.iter() {
+        if !character.char.is_control() {
+            new_name.name.push(character.char);
+            new_name.error = None;
+        }
+    }
+    if input.just_pressed(KeyCode::Back) {
+        new_name.name.pop();
+        new_name.error = None;
+    }
+    if input.just_pressed(KeyCode::Return) {
+        create_profile.send(CreateProfileEvent);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_profile_event(
+    mut create_profile_event: EventReader<CreateProfileEvent>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+    mut profiles: ResMut<Profiles>,
+    mut new_name: ResMut<NewProfileName>,
+    mut profile: ResMut<Profile>,
+    mut settings: ResMut<Settings>,
+    mut save_data: ResMut<SaveData>,
+) {
+    if create_profile_event.iter().count() == 0 {
+        return;
+    }
+
+    match profiles.add(&new_name.name) {
+        Ok(created) => {
+            let active = (&mut *profile, &mut *settings, &mut *save_data);
+            use_profile(created, active, &mut profiles, &mut restart_audio, &mut change_game_state);
+        }
+        Err(err) => new_name.error = Some(err.to_string()),
+    }
+}
+
+// Using Belly, draw the buttons of the new profile screen. The name is shown separately as it's
+// typed.
+pub fn draw_new_profile_ui(
+    mut commands: Commands,
+) {
+    let back = commands.spawn_empty().id();
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:tuning-check-wrapper>
+                <button on:press=|ctx| ctx.send_event(BackEvent) c:control><img {back} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+                <button c:setting on:press=|ctx| ctx.send_event(CreateProfileEvent)><strong>"Create Profile"</strong></button>
+            </div>
+        </body>
+    });
+}
+
+pub fn spawn_new_profile_name(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                top: Val::Percent(20.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        },
+        NewProfileUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 36.0,
+                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            NewProfileUI));
+    });
+}
+
+pub fn update_new_profile_name(
+    mut name_text: Query<&mut Text, With<NewProfileUI>>,
+    new_name: Res<NewProfileName>,
+) {
+    let mut lines = vec![
+        "Type a name for the new profile".to_string(),
+        format!("{}_", new_name.name),
+    ];
+    if let Some(error) = &new_name.error {
+        lines.push(format!("Can't make that profile, {}", error));
+    }
+    let status = lines.join("\n");
+
+    for mut text in name_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = status.clone();
+        }
+    }
+}
+
+pub fn despawn_new_profile_name(
+    mut commands: Commands,
+    name_query: Query<Entity, (With<NewProfileUI>, Without<Text>)>,
+) {
+    for name in name_query.iter() {
+        commands.entity(name).despawn_recursive();
+    }
+}
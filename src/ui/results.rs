@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use belly::prelude::*;
+use crate::GameState;
+use crate::input::Grade;
+use crate::level::LevelResource;
+use crate::save_data::{LevelQuit, SaveData};
+use crate::scoring::{LevelScore, LevelStats};
+
+// RetryEvent plays the level that just ended again, ResultsBackEvent goes back to the main menu
+#[derive(Event)]
+pub struct RetryEvent;
+
+#[derive(Event)]
+pub struct ResultsBackEvent;
+
+// The level's cleared away by the time Ending has run, so show how it went. A level quit from the
+// pause menu has no results and goes straight back to the main menu.
+pub fn load_results(
+    mut commands: Commands,
+    quit: Option<Res<LevelQuit>>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    if quit.is_some() {
+        commands.remove_resource::<LevelQuit>();
+        change_game_state.set(GameState::MenuAssetLoading);
+    } else {
+        change_game_state.set(GameState::Results);
+    }
+}
+
+// The level's path is still in LevelResource so loading it again starts it over
+pub fn retry_event(
+    mut retry_event: EventReader<RetryEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _retry in retry_event.iter() {
+        change_game_state.set(GameState::AssetLoading);
+    }
+}
+
+pub fn results_back_event(
+    mut back_event: EventReader<ResultsBackEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _back in back_event.iter() {
+        change_game_state.set(GameState::MenuAssetLoading);
+    }
+}
+
+// Using Belly, draw the results of the level with Retry and Back buttons. The score isn't reset
+// until the next level loads so it's all still there.
+pub fn draw_results_ui(
+    mut commands: Commands,
+    score: Res<LevelScore>,
+    stats: Res<LevelStats>,
+    save_data: Res<SaveData>,
+    level: Res<LevelResource>,
+) {
+    let grade = stats.letter_grade().to_string();
+    let mut lines = vec![
+        format!("Score {}", score.points),
+        format!("Accuracy {:.1}%", stats.accuracy()),
+        format!(
+            "Perfect {}  Great {}  Good {}",
+            stats.count(Grade::Perfect),
+            stats.count(Grade::Great),
+            stats.count(Grade::Good),
+        ),
+        format!("Misses {}", stats.count(Grade::Miss)),
+        format!("Max streak {}", score.max_streak),
+    ];
+    if let Some(song) = save_data.song(&level.0) {
+        if song.new_best_score() {
+            lines.push("New best score!".to_string());
+        }
+        if song.new_best_accuracy() {
+            lines.push("New best accuracy!".to_string());
+        }
+        lines.push(format!("Best {} ({:.1}%) over {} plays", song.best_score, song.best_accuracy, song.plays));
+    }
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:results-wrapper>
+                <strong c:letter-grade>{grade}</strong>
+                <for line in=lines>
+                    <strong c:result>{line}</strong>
+                </for>
+                <div c:setting-row>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(RetryEvent)><strong>"Retry"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(ResultsBackEvent)><strong>"Back"</strong></button>
+                </div>
+            </div>
+        </body>
+    });
+}
@@ -1,9 +1,16 @@
 use bevy::prelude::*;
-use crate::LevelScore;
+use crate::scoring::LevelScore;
+use crate::input::{Grade, JudgementEvent};
+
+// how long each grade stays up, fading out as it goes
+const JUDGEMENT_SECONDS: f32 = 0.6;
 
 #[derive(Component)]
 pub struct ScoreUI;
 
+#[derive(Component)]
+pub struct JudgementUI;
+
 pub fn spawn_score(
     mut commands: Commands,
 ) {
@@ -23,15 +30,26 @@ pub fn spawn_score(
         },
         ScoreUI,
     )).with_children(|parent| {
+            // the score, then its multiplier and the streak building it in smaller text
             parent.spawn((TextBundle {
-                text: Text::from_section(
-                    "0",
-                    TextStyle {
-                        font_size: 60.0,
-                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
-                        ..default()
-                    }
-                ),
+                text: Text::from_sections([
+                    TextSection::new(
+                        "0",
+                        TextStyle {
+                            font_size: 60.0,
+                            color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                            ..default()
+                        }
+                    ),
+                    TextSection::new(
+                        "",
+                        TextStyle {
+                            font_size: 30.0,
+                            color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                            ..default()
+                        }
+                    ),
+                ]),
                 ..default()
             }.with_style(Style {
                  ..default()   
@@ -44,9 +62,89 @@ pub fn update_score(
     mut score_text: Query<&mut Text, With<Label>>,
     score: Res<LevelScore>,
 ) {
-    let mut sections = score_text.single_mut();
-    for section in sections.sections.iter_mut() {
-        section.value = score.0.to_string();
+    let mut text = score_text.single_mut();
+    text.sections[0].value = score.points.to_string();
+    text.sections[1].value = format!("  {}x  Streak {}", score.multiplier, score.streak);
+}
+
+// The grade of the latest note, with how early or late it was played, over the middle of the screen
+pub fn spawn_judgement(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                top: Val::Percent(25.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        },
+        JudgementUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 48.0,
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            JudgementUI));
+    });
+}
+
+fn grade_color(grade: Grade) -> Color {
+    match grade {
+        Grade::Perfect => Color::rgb(0., 0.9098039215686274, 1.),
+        Grade::Great => Color::rgb(0.2, 1., 0.4),
+        Grade::Good => Color::rgb(1., 0.85, 0.2),
+        Grade::Miss => Color::rgb(1., 0.3, 0.3),
+    }
+}
+
+pub fn update_judgement(
+    mut judgement_events: EventReader<JudgementEvent>,
+    mut judgement_text: Query<&mut Text, With<JudgementUI>>,
+    mut remaining: Local<f32>,
+    time: Res<Time>,
+) {
+    let latest = judgement_events.iter().last();
+    *remaining = (*remaining - time.delta_seconds()).max(0.);
+
+    for mut text in judgement_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            if let Some(judgement) = latest {
+                section.value = match judgement.offset {
+                    Some(offset) => format!(
+                        "{} - {:.0} ms {}",
+                        judgement.grade.name(),
+                        offset.abs() * 1000.,
+                        if offset < 0. { "early" } else { "late" },
+                    ),
+                    None => judgement.grade.name().to_string(),
+                };
+                section.style.color = grade_color(judgement.grade);
+            }
+            let alpha = if latest.is_some() { 1. } else { *remaining / JUDGEMENT_SECONDS };
+            section.style.color.set_a(alpha);
+        }
+    }
+    if latest.is_some() {
+        *remaining = JUDGEMENT_SECONDS;
+    }
+}
+
+pub fn despawn_judgement(
+    mut commands: Commands,
+    judgement_query: Query<Entity, (With<JudgementUI>, Without<Text>)>,
+) {
+    for judgement in judgement_query.iter() {
+        commands.entity(judgement).despawn_recursive();
     }
 }
 
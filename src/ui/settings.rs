@@ -0,0 +1,602 @@
+use bevy::prelude::*;
+use belly::prelude::*;
+use crate::GameState;
+use crate::bass::device::{channel_label, input_options, InputOption};
+use crate::bass::pitch_detector::{amplitude_to_decibels, AudioStream, DetectorAlgorithm, InputLevelEvent, RestartAudioEvent};
+use crate::bass::source::InputSource;
+use crate::bass::tuning::PRESETS;
+use crate::profile::Profile;
+use crate::settings::{InputSettings, Settings};
+use crate::ui::calibration::StringCalibrationEvent;
+use crate::ui::latency::LatencyTestEvent;
+
+// SettingsEvent used to open the settings screen from the main menu
+#[derive(Event)]
+pub struct SettingsEvent;
+
+// BackEvent used to return to the main menu from a menu screen
+#[derive(Event)]
+pub struct BackEvent;
+
+// InputSelectEvent carries the input channel the player picked
+#[derive(Event)]
+pub struct InputSelectEvent(pub InputOption);
+
+// AlgorithmSelectEvent carries the pitch detection algorithm the player picked
+#[derive(Event)]
+pub struct AlgorithmSelectEvent(pub DetectorAlgorithm);
+
+// GainEvent and NoiseGateEvent carry how many dB to move the input gain or noise gate by
+#[derive(Event)]
+pub struct GainEvent(pub f64);
+
+#[derive(Event)]
+pub struct NoiseGateEvent(pub f64);
+
+// AutoGainEvent starts the gain calibration
+#[derive(Event)]
+pub struct AutoGainEvent;
+
+// HumFilterEvent carries the mains frequency to notch out, DcBlockerEvent and BandPassEvent turn
+// those filters on or off
+#[derive(Event)]
+pub struct HumFilterEvent(pub Option<f64>);
+
+#[derive(Event)]
+pub struct DcBlockerEvent;
+
+#[derive(Event)]
+pub struct BandPassEvent;
+
+// TuningCheckToggleEvent turns the tuning check before each level on or off
+#[derive(Event)]
+pub struct TuningCheckToggleEvent;
+
+// TuningSelectEvent moves on to the next tuning preset for the player's bass
+#[derive(Event)]
+pub struct TuningSelectEvent;
+
+// DifficultyEvent moves on to the next difficulty, which picks the scoring rules levels use
+#[derive(Event)]
+pub struct DifficultyEvent;
+
+#[derive(Component)]
+pub struct InputStatusUI;
+
+#[derive(Component)]
+pub struct InputMeterUI;
+
+#[derive(Component)]
+pub struct InputMeterFill;
+
+#[derive(Component)]
+pub struct InputMeterGate;
+
+const GAIN_STEP_DB: f64 = 3.;
+const NOISE_GATE_STEP_DB: f64 = 3.;
+// the meter runs from this up to full scale
+const METER_FLOOR_DB: f64 = -60.;
+
+// Gain calibration listens to the strings muted and then played as hard as the player plays.
+// The loudest notes get the gain to put them at TARGET_PEAK_DB and the noise gate goes
+// GATE_HEADROOM_DB over the noise heard with the strings muted.
+#[derive(Resource)]
+pub struct GainCalibration {
+    phase: CalibrationPhase,
+    timer: Timer,
+    // hop levels heard so far, before the gain
+    noise_sum: f64,
+    noise_count: usize,
+    peak: f64,
+}
+
+enum CalibrationPhase {
+    Muted,
+    Playing,
+    Finished(String),
+}
+
+impl GainCalibration {
+    const MUTED_SECONDS: f32 = 3.;
+    const PLAYING_SECONDS: f32 = 6.;
+    const TARGET_PEAK_DB: f64 = -12.;
+    const GATE_HEADROOM_DB: f64 = 10.;
+    const MAX_GAIN_DB: f64 = 40.;
+}
+
+pub fn settings_event(
+    mut settings_event: EventReader<SettingsEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _settings in settings_event.iter() {
+        change_game_state.set(GameState::Settings);
+    }
+}
+
+// The main menu is drawn when its assets finish loading, so go back through the loading state
+pub fn back_event(
+    mut back_event: EventReader<BackEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _back in back_event.iter() {
+        change_game_state.set(GameState::MenuAssetLoading);
+    }
+}
+
+// Save the picked input and reopen the stream on it
+pub fn input_select_event(
+    mut input_select_event: EventReader<InputSelectEvent>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    for input in input_select_event.iter() {
+        settings.input = InputSettings {
+            host: Some(input.0.host.clone()),
+            device: Some(input.0.device.clone()),
+            channel: input.0.channel,
+        };
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+        restart_audio.send(RestartAudioEvent);
+    }
+}
+
+// Save the picked algorithm and restart the detector with it
+pub fn algorithm_select_event(
+    mut algorithm_select_event: EventReader<AlgorithmSelectEvent>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    for algorithm in algorithm_select_event.iter() {
+        settings.detector.algorithm = algorithm.0;
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+        restart_audio.send(RestartAudioEvent);
+    }
+}
+
+// Move the gain or noise gate of the device in use
+pub fn gate_event(
+    mut gain_event: EventReader<GainEvent>,
+    mut noise_gate_event: EventReader<NoiseGateEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+    audio_stream: Option<Res<AudioStream>>,
+) {
+    let Some(stream) = audio_stream else { return };
+    let mut changed = false;
+
+    for gain in gain_event.iter() {
+        let gate = settings.gate_mut(&stream.device);
+        gate.gain_db = (gate.gain_db + gain.0).clamp(-GainCalibration::MAX_GAIN_DB, GainCalibration::MAX_GAIN_DB);
+        changed = true;
+    }
+    for noise_gate in noise_gate_event.iter() {
+        let gate = settings.gate_mut(&stream.device);
+        gate.noise_gate_db = (gate.noise_gate_db + noise_gate.0).clamp(METER_FLOOR_DB, 0.);
+        changed = true;
+    }
+
+    if changed {
+        stream.set_gate(&settings.gate(&stream.device));
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+// Save the filter changes and restart the detector with them
+pub fn filter_event(
+    mut hum_filter_event: EventReader<HumFilterEvent>,
+    mut dc_blocker_event: EventReader<DcBlockerEvent>,
+    mut band_pass_event: EventReader<BandPassEvent>,
+    mut restart_audio: EventWriter<RestartAudioEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    let mut changed = false;
+
+    for hum_filter in hum_filter_event.iter() {
+        settings.filter.mains_frequency = hum_filter.0;
+        changed = true;
+    }
+    for _dc_blocker in dc_blocker_event.iter() {
+        settings.filter.dc_blocker = !settings.filter.dc_blocker;
+        changed = true;
+    }
+    for _band_pass in band_pass_event.iter() {
+        settings.filter.band_pass = !settings.filter.band_pass;
+        changed = true;
+    }
+
+    if changed {
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+        restart_audio.send(RestartAudioEvent);
+    }
+}
+
+pub fn tuning_check_toggle_event(
+    mut tuning_check_toggle_event: EventReader<TuningCheckToggleEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    for _toggle in tuning_check_toggle_event.iter() {
+        settings.skip_tuning_check = !settings.skip_tuning_check;
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+// The tuner and string calibration use the new tuning straight away
+pub fn tuning_select_event(
+    mut commands: Commands,
+    mut tuning_select_event: EventReader<TuningSelectEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    for _tuning in tuning_select_event.iter() {
+        let current = settings.tuning();
+        let next = PRESETS.iter().position(|name| *name == current.name).map_or(0, |index| (index + 1) % PRESETS.len());
+        settings.tuning = Some(PRESETS[next].to_string());
+        commands.insert_resource(settings.tuning());
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+pub fn difficulty_event(
+    mut difficulty_event: EventReader<DifficultyEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+) {
+    for _difficulty in difficulty_event.iter() {
+        settings.difficulty = settings.difficulty.next();
+        if let Err(err) = settings.save(&profile) {
+            println!("Couldn't save settings: {}", err);
+        }
+    }
+}
+
+pub fn auto_gain_event(
+    mut commands: Commands,
+    mut auto_gain_event: EventReader<AutoGainEvent>,
+) {
+    for _auto_gain in auto_gain_event.iter() {
+        commands.insert_resource(GainCalibration {
+            phase: CalibrationPhase::Muted,
+            timer: Timer::from_seconds(GainCalibration::MUTED_SECONDS, TimerMode::Once),
+            noise_sum: 0.,
+            noise_count: 0,
+            peak: 0.,
+        });
+    }
+}
+
+pub fn update_gain_calibration(
+    time: Res<Time>,
+    mut level_events: EventReader<InputLevelEvent>,
+    mut settings: ResMut<Settings>,
+    profile: Res<Profile>,
+    calibration: Option<ResMut<GainCalibration>>,
+    audio_stream: Option<Res<AudioStream>>,
+) {
+    let (Some(mut calibration), Some(stream)) = (calibration, audio_stream) else {
+        level_events.clear();
+        return;
+    };
+    let calibration = &mut *calibration;
+    let gate = settings.gate(&stream.device);
+
+    // undo the gain so the new one doesn't depend on the old one
+    for level in level_events.iter() {
+        let level = level.0 / gate.gain();
+        match calibration.phase {
+            CalibrationPhase::Muted => {
+                calibration.noise_sum += level;
+                calibration.noise_count += 1;
+            }
+            CalibrationPhase::Playing => calibration.peak = calibration.peak.max(level),
+            CalibrationPhase::Finished(_) => (),
+        }
+    }
+
+    // the game clock stays paused after a level, so tick on real time
+    calibration.timer.tick(time.raw_delta());
+    if !calibration.timer.just_finished() {
+        return;
+    }
+
+    match calibration.phase {
+        CalibrationPhase::Muted => {
+            calibration.phase = CalibrationPhase::Playing;
+            calibration.timer = Timer::from_seconds(GainCalibration::PLAYING_SECONDS, TimerMode::Once);
+        }
+        CalibrationPhase::Playing => {
+            let noise_floor = calibration.noise_sum / calibration.noise_count.max(1) as f64;
+            let noise_floor_db = amplitude_to_decibels(noise_floor);
+            let peak_db = amplitude_to_decibels(calibration.peak);
+
+            // notes have to stand well clear of the noise for any gate to tell them apart
+            if peak_db - noise_floor_db < GainCalibration::GATE_HEADROOM_DB * 2. {
+                calibration.phase = CalibrationPhase::Finished("Couldn't hear the bass over the noise, try again".to_string());
+                return;
+            }
+
+            let gain_db = (GainCalibration::TARGET_PEAK_DB - peak_db)
+                .clamp(-GainCalibration::MAX_GAIN_DB, GainCalibration::MAX_GAIN_DB);
+            let gate = settings.gate_mut(&stream.device);
+            gate.gain_db = gain_db.round();
+            gate.noise_gate_db = (noise_floor_db + gate.gain_db + GainCalibration::GATE_HEADROOM_DB).round();
+            stream.set_gate(gate);
+            calibration.phase = CalibrationPhase::Finished("Calibrated".to_string());
+
+            if let Err(err) = settings.save(&profile) {
+                println!("Couldn't save settings: {}", err);
+            }
+        }
+        CalibrationPhase::Finished(_) => (),
+    }
+}
+
+pub fn end_gain_calibration(
+    mut commands: Commands,
+) {
+    commands.remove_resource::<GainCalibration>();
+}
+
+// Using Belly, list every input channel the detector can listen to and every detection algorithm,
+// along with the level, filter and calibration controls.
+pub fn draw_settings_ui(
+    mut commands: Commands,
+) {
+    let back = commands.spawn_empty().id();
+
+    // Fetch inputs
+    let inputs = input_options();
+    let algorithms = DetectorAlgorithm::ALL.to_vec();
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:settings-wrapper>
+                <button on:press=|ctx| ctx.send_event(BackEvent) c:control><img {back} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+                <for input in=inputs>
+                    <button c:setting on:press=move |ctx| ctx.send_event(InputSelectEvent(input.clone()))><strong>{input.label()}</strong></button>
+                </for>
+                <for algorithm in=algorithms>
+                    <button c:setting on:press=move |ctx| ctx.send_event(AlgorithmSelectEvent(algorithm))><strong>{format!("Detector: {}", algorithm.name())}</strong></button>
+                </for>
+                <div c:setting-row>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(GainEvent(-GAIN_STEP_DB))><strong>"Gain -"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(GainEvent(GAIN_STEP_DB))><strong>"Gain +"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(NoiseGateEvent(-NOISE_GATE_STEP_DB))><strong>"Gate -"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(NoiseGateEvent(NOISE_GATE_STEP_DB))><strong>"Gate +"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(AutoGainEvent)><strong>"Auto Gain"</strong></button>
+                </div>
+                <div c:setting-row>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(HumFilterEvent(None))><strong>"Hum Off"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(HumFilterEvent(Some(50.)))><strong>"Hum 50 Hz"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(HumFilterEvent(Some(60.)))><strong>"Hum 60 Hz"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(DcBlockerEvent)><strong>"DC Blocker"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(BandPassEvent)><strong>"Band-pass"</strong></button>
+                </div>
+                <div c:setting-row>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(StringCalibrationEvent)><strong>"Calibrate Strings"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(TuningCheckToggleEvent)><strong>"Tuning Check"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(LatencyTestEvent)><strong>"Latency Test"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(DifficultyEvent)><strong>"Difficulty"</strong></button>
+                    <button c:setting-small on:press=|ctx| ctx.send_event(TuningSelectEvent)><strong>"Bass Tuning"</strong></button>
+                </div>
+            </div>
+        </body>
+    });
+}
+
+// The input in use is shown with regular bevy UI so it can change while the screen is open
+pub fn spawn_input_status(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                padding: UiRect::px(0., 0., 5., 0.),
+                ..default()
+            },
+            ..default()
+        },
+        InputStatusUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            InputStatusUI));
+    });
+}
+
+pub fn update_input_status(
+    mut status_text: Query<&mut Text, With<InputStatusUI>>,
+    audio_stream: Option<Res<AudioStream>>,
+    settings: Res<Settings>,
+    source: Res<InputSource>,
+) {
+    let status = match audio_stream {
+        // picking an input does nothing while a file or test signal is playing instead
+        Some(stream) if source.is_offline() => format!(
+            "Playing {} ({})",
+            stream.device, settings.detector.algorithm.name()
+        ),
+        Some(stream) => format!(
+            "Listening to {} - {} ({})",
+            stream.device, channel_label(stream.channel), settings.detector.algorithm.name()
+        ),
+        None => "No input device".to_string(),
+    };
+
+    for mut text in status_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = status.clone();
+        }
+    }
+}
+
+pub fn despawn_input_status(
+    mut commands: Commands,
+    status_query: Query<Entity, (With<InputStatusUI>, Without<Text>)>,
+) {
+    for status in status_query.iter() {
+        commands.entity(status).despawn_recursive();
+    }
+}
+
+// The input meter, with the noise gate marked on it, is regular bevy UI along the bottom
+pub fn spawn_input_meter(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                bottom: Val::Percent(3.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        },
+        InputMeterUI,
+    )).with_children(|parent| {
+        parent.spawn((TextBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                    ..default()
+                }
+            ),
+            ..default()
+        },
+        InputMeterUI));
+        parent.spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(50.0),
+                height: Val::Px(20.0),
+                ..default()
+            },
+            background_color: Color::rgb(0.0078, 0.2078, 0.4666).into(),
+            ..default()
+        }).with_children(|meter| {
+            meter.spawn((NodeBundle {
+                style: Style {
+                    width: Val::Percent(0.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0., 0.9098039215686274, 1.).into(),
+                ..default()
+            },
+            InputMeterFill));
+            meter.spawn((NodeBundle {
+                style: Style {
+                    width: Val::Px(3.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    ..default()
+                },
+                background_color: Color::RED.into(),
+                ..default()
+            },
+            InputMeterGate));
+        });
+    });
+}
+
+fn meter_percent(decibels: f64) -> f32 {
+    ((decibels - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0., 1.) as f32 * 100.
+}
+
+pub fn update_input_meter(
+    mut level_events: EventReader<InputLevelEvent>,
+    mut fill_query: Query<&mut Style, (With<InputMeterFill>, Without<InputMeterGate>)>,
+    mut gate_query: Query<&mut Style, (With<InputMeterGate>, Without<InputMeterFill>)>,
+    mut text_query: Query<&mut Text, With<InputMeterUI>>,
+    mut level: Local<f64>,
+    settings: Res<Settings>,
+    profile: Res<Profile>,
+    audio_stream: Option<Res<AudioStream>>,
+    calibration: Option<Res<GainCalibration>>,
+) {
+    // jump up to peaks straight away and fall back slowly so the meter is readable
+    *level *= 0.9;
+    for event in level_events.iter() {
+        *level = level.max(event.0);
+    }
+
+    let Some(stream) = audio_stream else { return };
+    let gate = settings.gate(&stream.device);
+
+    for mut style in fill_query.iter_mut() {
+        style.width = Val::Percent(meter_percent(amplitude_to_decibels(*level)));
+    }
+    for mut style in gate_query.iter_mut() {
+        style.left = Val::Percent(meter_percent(gate.noise_gate_db));
+    }
+
+    let on_off = |on: bool| if on { "on" } else { "off" };
+    let filters = format!(
+        "Hum filter {}, DC blocker {}, Band-pass {}, Tuning check {}, Latency {} ms, Difficulty {}",
+        settings.filter.mains_frequency.map_or("off".to_string(), |mains| format!("{} Hz", mains)),
+        on_off(settings.filter.dc_blocker),
+        on_off(settings.filter.band_pass),
+        on_off(!settings.skip_tuning_check),
+        settings.latency_ms,
+        settings.difficulty.name(),
+    );
+    let label = match calibration.as_deref().map(|calibration| (&calibration.phase, calibration.timer.remaining_secs().ceil())) {
+        Some((CalibrationPhase::Muted, seconds)) => format!("Mute the strings... {}", seconds),
+        Some((CalibrationPhase::Playing, seconds)) => format!("Play every string as hard as you play... {}", seconds),
+        Some((CalibrationPhase::Finished(message), _)) => format!("{} - Gain {:+} dB, Noise gate {} dB", message, gate.gain_db, gate.noise_gate_db),
+        None => format!(
+            "Gain {:+} dB, Noise gate {} dB\n{}\nProfile {}, Bass tuning {}",
+            gate.gain_db,
+            gate.noise_gate_db,
+            filters,
+            profile.name,
+            settings.tuning().name,
+        ),
+    };
+    for mut text in text_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = label.clone();
+        }
+    }
+}
+
+pub fn despawn_input_meter(
+    mut commands: Commands,
+    meter_query: Query<Entity, (With<InputMeterUI>, Without<Text>)>,
+) {
+    for meter in meter_query.iter() {
+        commands.entity(meter).despawn_recursive();
+    }
+}
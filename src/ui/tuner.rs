@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+use bevy::utils::Instant;
+use belly::prelude::*;
+use crate::GameState;
+use crate::bass::note::{frequency_to_midi, note_name};
+use crate::bass::pitch_detector::PitchEvent;
+use crate::bass::tuning::Tuning;
+use crate::ui::settings::BackEvent;
+
+// close enough to call the string in tune
+const IN_TUNE_CENTS: f64 = 5.;
+// the needle runs from this flat to this sharp
+const NEEDLE_RANGE_CENTS: f64 = 50.;
+// how long the last note stays up after the string stops ringing
+const HOLD_SECONDS: f64 = 1.5;
+// how far each estimate moves the needle, lower is steadier but slower
+const SMOOTHING: f64 = 0.3;
+
+const TUNER_BLUE: Color = Color::rgb(0., 0.9098039215686274, 1.);
+const IN_TUNE_GREEN: Color = Color::rgb(0.2, 1., 0.4);
+
+// TunerEvent opens the tuner from the main menu
+#[derive(Event)]
+pub struct TunerEvent;
+
+#[derive(Component)]
+pub struct TunerUI;
+
+#[derive(Component)]
+pub struct TunerNote;
+
+#[derive(Component)]
+pub struct TunerDetail;
+
+#[derive(Component)]
+pub struct TunerNeedle;
+
+// The pitch being shown, smoothed over the last few estimates
+#[derive(Default)]
+pub struct TunerReading {
+    midi: f64,
+    frequency: f64,
+    last_heard: Option<Instant>,
+}
+
+pub fn tuner_event(
+    mut tuner_event: EventReader<TunerEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _tuner in tuner_event.iter() {
+        change_game_state.set(GameState::Tuner);
+    }
+}
+
+// Using Belly, draw the back button. The tuner itself is regular bevy UI so it can move every frame.
+pub fn draw_tuner_ui(
+    mut commands: Commands,
+) {
+    let back = commands.spawn_empty().id();
+
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:tuner-wrapper>
+                <button on:press=|ctx| ctx.send_event(BackEvent) c:control><img {back} src="textures/Back-Logo.png" mode="fit" c:image/></button>
+            </div>
+        </body>
+    });
+}
+
+pub fn spawn_tuner(
+    mut commands: Commands,
+) {
+    let text_style = |font_size: f32| TextStyle {
+        font_size,
+        color: TUNER_BLUE,
+        ..default()
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                top: Val::Percent(35.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        },
+        TunerUI,
+    )).with_children(|parent| {
+        parent.spawn((TextBundle {
+            text: Text::from_section("-", text_style(120.0)),
+            ..default()
+        },
+        TunerNote));
+        parent.spawn((TextBundle {
+            text: Text::from_section("Play a string", text_style(30.0)),
+            ..default()
+        },
+        TunerDetail));
+        parent.spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(50.0),
+                height: Val::Px(30.0),
+                margin: UiRect::top(Val::Px(20.0)),
+                ..default()
+            },
+            background_color: Color::rgb(0.0078, 0.2078, 0.4666).into(),
+            ..default()
+        }).with_children(|scale| {
+            // dead centre, where the needle wants to be
+            scale.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(2.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(50.0),
+                    ..default()
+                },
+                background_color: Color::WHITE.into(),
+                ..default()
+            });
+            scale.spawn((NodeBundle {
+                style: Style {
+                    width: Val::Px(6.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    left: Val::Percent(50.0),
+                    display: Display::None,
+                    ..default()
+                },
+                background_color: TUNER_BLUE.into(),
+                ..default()
+            },
+            TunerNeedle));
+        });
+    });
+}
+
+// Shows the nearest note, the string of the current tuning it's closest to and how many cents off
+// it is
+pub fn update_tuner(
+    mut pitch_events: EventReader<PitchEvent>,
+    mut reading: Local<TunerReading>,
+    mut note_query: Query<&mut Text, (With<TunerNote>, Without<TunerDetail>)>,
+    mut detail_query: Query<&mut Text, (With<TunerDetail>, Without<TunerNote>)>,
+    mut needle_query: Query<(&mut Style, &mut BackgroundColor), With<TunerNeedle>>,
+    tuning: Res<Tuning>,
+) {
+    for pitch in pitch_events.iter() {
+        let midi = frequency_to_midi(pitch.frequency);
+        // start over on a new note rather than sweeping the needle across
+        let fresh = !reading.last_heard.is_some_and(|heard| heard.elapsed().as_secs_f64() <= HOLD_SECONDS);
+        if fresh || (midi - reading.midi).abs() > 0.5 {
+            reading.midi = midi;
+        } else {
+            reading.midi += (midi - reading.midi) * SMOOTHING;
+        }
+        reading.frequency = pitch.frequency;
+        reading.last_heard = Some(pitch.time);
+    }
+
+    let heard = reading.last_heard.is_some_and(|heard| heard.elapsed().as_secs_f64() <= HOLD_SECONDS);
+    let nearest = reading.midi.round() as i32;
+    let cents = (reading.midi - nearest as f64) * 100.;
+    let color = if cents.abs() <= IN_TUNE_CENTS { IN_TUNE_GREEN } else { TUNER_BLUE };
+
+    let (note, detail) = if heard {
+        let string = tuning.strings.iter()
+            .find(|string| string.midi == nearest)
+            .map_or(String::new(), |string| format!("{} string - ", string.name));
+        (note_name(nearest), format!("{}{:.1} Hz, {:+.0} cents", string, reading.frequency, cents))
+    } else {
+        ("-".to_string(), format!("Play a string ({} tuning)", tuning.name))
+    };
+
+    for mut text in note_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = note.clone();
+            section.style.color = if heard { color } else { TUNER_BLUE };
+        }
+    }
+    for mut text in detail_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = detail.clone();
+        }
+    }
+    for (mut style, mut background) in needle_query.iter_mut() {
+        style.display = if heard { Display::Flex } else { Display::None };
+        let position = 50. + 50. * (cents / NEEDLE_RANGE_CENTS).clamp(-1., 1.);
+        style.left = Val::Percent(position as f32);
+        *background = color.into();
+    }
+}
+
+pub fn despawn_tuner(
+    mut commands: Commands,
+    tuner_query: Query<Entity, With<TunerUI>>,
+) {
+    for tuner in tuner_query.iter() {
+        commands.entity(tuner).despawn_recursive();
+    }
+}
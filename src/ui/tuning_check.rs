@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use belly::prelude::*;
+use crate::GameState;
+use crate::bass::note::{cents_from_midi, frequency_to_midi, note_name};
+use crate::bass::pitch_detector::PitchEvent;
+use crate::bass::tuning::Tuning;
+use crate::settings::Settings;
+
+// how close an open string has to be to count as in tune. Tighter than the hit tolerance since
+// fretted notes pick up intonation errors on top.
+const IN_TUNE_CENTS: f64 = 10.;
+// estimates in a row that have to be in tune before the string is ticked off, so passing through
+// the right pitch while turning the peg doesn't count
+const STEADY_READINGS: usize = 8;
+// a pitch further than this from every open string is a fretted note or noise
+const MAX_DETUNE_SEMITONES: f64 = 2.5;
+
+// SkipTuningCheckEvent starts the level without checking the tuning
+#[derive(Event)]
+pub struct SkipTuningCheckEvent;
+
+#[derive(Component)]
+pub struct TuningCheckUI;
+
+struct StringCheck {
+    name: String,
+    midi: i32,
+    // how far off the last estimate was, None until the string is heard
+    cents: Option<f64>,
+    steady: usize,
+    in_tune: bool,
+}
+
+// Every open string of the chart's tuning and whether it's been heard in tune yet
+#[derive(Resource)]
+pub struct TuningCheck {
+    strings: Vec<StringCheck>,
+}
+
+impl TuningCheck {
+    fn all_in_tune(&self) -> bool {
+        self.strings.iter().all(|string| string.in_tune)
+    }
+}
+
+pub fn start_tuning_check(
+    mut commands: Commands,
+    tuning: Res<Tuning>,
+) {
+    commands.insert_resource(TuningCheck {
+        strings: tuning.strings.iter().map(|string| StringCheck {
+            name: string.name.clone(),
+            midi: string.midi,
+            cents: None,
+            steady: 0,
+            in_tune: false,
+        }).collect(),
+    });
+}
+
+pub fn end_tuning_check(
+    mut commands: Commands,
+) {
+    commands.remove_resource::<TuningCheck>();
+}
+
+pub fn skip_tuning_check_event(
+    mut skip_event: EventReader<SkipTuningCheckEvent>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+) {
+    for _skip in skip_event.iter() {
+        change_game_state.set(GameState::InGame);
+    }
+}
+
+// Matches each estimate to the nearest open string and starts the level once every string has
+// been heard in tune
+pub fn update_tuning_check(
+    mut pitch_events: EventReader<PitchEvent>,
+    mut check: ResMut<TuningCheck>,
+    mut change_game_state: ResMut<NextState<GameState>>,
+    settings: Res<Settings>,
+    tuning: Res<Tuning>,
+) {
+    for pitch in pitch_events.iter() {
+        let midi = frequency_to_midi(pitch.frequency);
+        let Some(string) = check.strings.iter_mut()
+            .filter(|string| (midi - string.midi as f64).abs() <= MAX_DETUNE_SEMITONES)
+            .min_by(|a, b| (midi - a.midi as f64).abs().total_cmp(&(midi - b.midi as f64).abs()))
+        else { continue };
+
+        // judged the same way as notes in the level, less the calibrated offset for the string
+        let cents = cents_from_midi(pitch.frequency, string.midi) - settings.string_offset(&tuning, &string.name, 0);
+        string.cents = Some(cents);
+        if cents.abs() <= IN_TUNE_CENTS {
+            string.steady += 1;
+            string.in_tune |= string.steady >= STEADY_READINGS;
+        } else {
+            string.steady = 0;
+        }
+    }
+
+    if check.all_in_tune() {
+        change_game_state.set(GameState::InGame);
+    }
+}
+
+// Using Belly, draw the skip button. The strings are listed separately as they change every frame.
+pub fn draw_tuning_check_ui(
+    mut commands: Commands,
+) {
+    commands.add(StyleSheet::load("stylesheets/Bass.ess"));
+    commands.add(eml! {
+        <body>
+            <div c:tuning-check-wrapper>
+                <button c:setting on:press=|ctx| ctx.send_event(SkipTuningCheckEvent)><strong>"Skip Tuning Check"</strong></button>
+            </div>
+        </body>
+    });
+}
+
+pub fn spawn_tuning_check_status(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                position_type: PositionType::Absolute,
+                top: Val::Percent(20.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        },
+        TuningCheckUI,
+    )).with_children(|parent| {
+            parent.spawn((TextBundle {
+                text: Text::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 36.0,
+                        color: Color::rgba(0., 0.9098039215686274, 1., 1.),
+                        ..default()
+                    }
+                ),
+                ..default()
+            },
+            TuningCheckUI));
+    });
+}
+
+pub fn update_tuning_check_status(
+    mut status_text: Query<&mut Text, With<TuningCheckUI>>,
+    check: Res<TuningCheck>,
+    tuning: Res<Tuning>,
+) {
+    let mut lines = vec![format!("Pluck each open string to check the {} tuning", tuning.name)];
+    // highest string on top, the way they sit when looking down at the bass
+    for string in check.strings.iter().rev() {
+        let state = match string.cents {
+            _ if string.in_tune => "in tune".to_string(),
+            None => "pluck it".to_string(),
+            Some(cents) if cents.abs() <= IN_TUNE_CENTS => "hold it...".to_string(),
+            Some(cents) if cents > 0. => format!("{:.0} cents sharp, tune down", cents),
+            Some(cents) => format!("{:.0} cents flat, tune up", -cents),
+        };
+        lines.push(format!("{} string ({}): {}", string.name, note_name(string.midi), state));
+    }
+    let status = lines.join("\n");
+
+    for mut text in status_text.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = status.clone();
+        }
+    }
+}
+
+pub fn despawn_tuning_check_status(
+    mut commands: Commands,
+    status_query: Query<Entity, (With<TuningCheckUI>, Without<Text>)>,
+) {
+    for status in status_query.iter() {
+        commands.entity(status).despawn_recursive();
+    }
+}